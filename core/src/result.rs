@@ -28,3 +28,4 @@ pub const EALREADY: isize = -3;
 pub const EINVAL: isize = -6;
 pub const ESIZE: isize = -7;
 pub const ENOMEM: isize = -9;
+pub const ENOSUPPORT: isize = -10;
@@ -13,14 +13,15 @@ async fn main() -> TockResult<()> {
     let timer_driver = timer_driver.activate()?;
     let mut console = drivers.console.create_console();
 
-    let mut callback = |channel, value| {
-        writeln!(console, "channel: {}, value: {}", channel, value).unwrap();
-    };
-
-    let _subscription = adc_driver.subscribe(&mut callback)?;
-
     loop {
-        adc_driver.sample(0)?;
+        let sample = adc_driver.read(0).await?;
+        writeln!(
+            console,
+            "channel: {}, value: {}",
+            sample.channel(),
+            sample.value()
+        )
+        .unwrap();
         timer_driver.sleep(Duration::from_ms(2000)).await?;
     }
 }
@@ -0,0 +1,32 @@
+#![no_std]
+
+use core::fmt::Write;
+use futures::stream::StreamExt;
+use libtock::buttons::ButtonEventBuffer;
+use libtock::result::TockResult;
+
+#[libtock::main]
+async fn main() -> TockResult<()> {
+    let mut drivers = libtock::retrieve_drivers()?;
+
+    let buttons_driver = drivers.buttons.init_driver()?;
+    let mut console = drivers.console.create_console();
+
+    let mut buffer = ButtonEventBuffer::default();
+    let mut events = buttons_driver.events(&mut buffer)?;
+
+    for button in buttons_driver.buttons() {
+        button.enable_interrupt()?;
+    }
+
+    while let Some((button_num, state)) = events.next().await {
+        writeln!(
+            console,
+            "button {}: {:?} (dropped: {})",
+            button_num,
+            state,
+            events.overflow_count()
+        )?;
+    }
+    Ok(())
+}
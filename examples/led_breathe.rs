@@ -0,0 +1,33 @@
+#![no_std]
+
+use libtock::pwm::LedEffect;
+use libtock::pwm::SoftPwm;
+use libtock::result::TockResult;
+use libtock::timer::Duration;
+
+#[libtock::main]
+async fn main() -> TockResult<()> {
+    let mut drivers = libtock::retrieve_drivers()?;
+
+    let leds_driver = drivers.leds.init_driver()?;
+    let mut timer_driver = drivers.timer.create_timer_driver();
+    let timer_driver = timer_driver.activate()?;
+
+    let mut pwm = SoftPwm::new([leds_driver.get(0)?], &timer_driver, Duration::from_ms(10))?;
+    let breathe = LedEffect::Breathe {
+        period: Duration::from_ms(2000),
+    };
+    let blink = LedEffect::Blink {
+        pattern: &[
+            Duration::from_ms(100),
+            Duration::from_ms(100),
+            Duration::from_ms(100),
+            Duration::from_ms(700),
+        ],
+    };
+
+    loop {
+        pwm.play(0, &breathe, 3).await?;
+        pwm.play(0, &blink, 3).await?;
+    }
+}
@@ -1,13 +1,26 @@
 use crate::callback::CallbackSubscription;
 use crate::callback::Consumer;
+use crate::result::OutOfRangeError;
 use crate::result::TockResult;
 use crate::shared_memory::SharedMemory;
 use crate::syscalls;
+use core::cell::Cell;
+use core::cell::UnsafeCell;
 use core::marker::PhantomData;
+use core::mem;
+use core::pin::Pin;
+use core::ptr;
+use core::slice;
+use core::task::Context;
+use core::task::Poll;
+use futures::stream::Stream;
 
 pub const DRIVER_NUMBER: usize = 0x0005;
 pub const BUFFER_SIZE: usize = 128;
 
+/// Largest resolution accepted by [Adc::channel_with_reference()]
+pub const MAX_RESOLUTION_BITS: usize = 24;
+
 mod command_nr {
     pub const COUNT: usize = 0;
     pub const START: usize = 1;
@@ -15,6 +28,8 @@ mod command_nr {
     pub const START_REPEAT_BUFFER: usize = 3;
     pub const START_REPEAT_BUFFER_ALT: usize = 4;
     pub const STOP: usize = 5;
+    pub const GET_RESOLUTION_BITS: usize = 101;
+    pub const GET_REFERENCE_VOLTAGE: usize = 102;
 }
 
 mod subscribe_nr {
@@ -58,6 +73,24 @@ pub struct Adc<'a> {
     lifetime: PhantomData<&'a ()>,
 }
 
+/// Result of a single conversion
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AdcSample {
+    channel: usize,
+    value: usize,
+}
+
+impl AdcSample {
+    pub fn channel(self) -> usize {
+        self.channel
+    }
+
+    /// The raw conversion result
+    pub fn value(self) -> usize {
+        self.value
+    }
+}
+
 struct AdcEventConsumer;
 
 impl<CB: FnMut(usize, usize)> Consumer<CB> for AdcEventConsumer {
@@ -99,6 +132,109 @@ impl<'a> Adc<'a> {
         Ok(())
     }
 
+    /// Samples `channel` once and waits for the result.
+    ///
+    /// The driver has a single callback slot, so this removes any callback
+    /// registered with [subscribe()](Self::subscribe) or
+    /// [stream()](Self::stream). The slot is left empty on return and such a
+    /// callback has to be subscribed again to receive further samples.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use libtock::result::TockResult;
+    /// # async fn doc() -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let adc_driver = drivers.adc.init_driver()?;
+    /// let potentiometer = adc_driver.read(0).await?;
+    /// let [x, y] = adc_driver.read_many([1, 2]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn read(&self, channel: usize) -> TockResult<AdcSample> {
+        if channel >= self.num_channels {
+            return Err(OutOfRangeError.into());
+        }
+        let value = Cell::new(None);
+        let mut callback = |sample_channel, sample_value| {
+            if sample_channel == channel {
+                value.set(Some(sample_value));
+            }
+        };
+        let subscription = syscalls::subscribe::<AdcEventConsumer, _>(
+            DRIVER_NUMBER,
+            subscribe_nr::SUBSCRIBE_CALLBACK,
+            &mut callback,
+        )?;
+        self.sample(channel)?;
+        let sample = AdcSample {
+            channel,
+            value: crate::futures::wait_for_value(|| value.get()).await,
+        };
+        mem::drop(subscription);
+        Ok(sample)
+    }
+
+    /// Samples each of `channels` once, one after another. Removes callbacks
+    /// like [read()](Self::read).
+    pub async fn read_many<const N: usize>(
+        &self,
+        channels: [usize; N],
+    ) -> TockResult<[AdcSample; N]> {
+        let mut samples = [AdcSample {
+            channel: 0,
+            value: 0,
+        }; N];
+        for (sample, &channel) in samples.iter_mut().zip(channels.iter()) {
+            *sample = self.read(channel).await?;
+        }
+        Ok(samples)
+    }
+
+    /// Resolution of the converter in bits as reported by the kernel
+    pub fn resolution_bits(&self) -> TockResult<usize> {
+        syscalls::command(DRIVER_NUMBER, command_nr::GET_RESOLUTION_BITS, 0, 0).map_err(Into::into)
+    }
+
+    /// Reference voltage of the converter in millivolts as reported by the
+    /// kernel
+    pub fn reference_voltage_mv(&self) -> TockResult<usize> {
+        syscalls::command(DRIVER_NUMBER, command_nr::GET_REFERENCE_VOLTAGE, 0, 0)
+            .map_err(Into::into)
+    }
+
+    /// Creates an [AdcChannel] for `channel`, querying the reference voltage
+    /// and resolution from the kernel
+    pub fn channel(&self, channel: usize) -> TockResult<AdcChannel> {
+        let reference_mv = self.reference_voltage_mv()?;
+        let resolution_bits = self.resolution_bits()?;
+        Ok(self.channel_with_reference(channel, reference_mv, resolution_bits)?)
+    }
+
+    /// Creates an [AdcChannel] for `channel` with a known reference voltage
+    /// and resolution, for kernels that cannot report them or boards using an
+    /// external reference
+    pub fn channel_with_reference(
+        &self,
+        channel: usize,
+        reference_mv: usize,
+        resolution_bits: usize,
+    ) -> Result<AdcChannel, OutOfRangeError> {
+        if channel >= self.num_channels
+            || reference_mv > i32::max_value() as usize
+            || resolution_bits == 0
+            || resolution_bits > MAX_RESOLUTION_BITS
+        {
+            return Err(OutOfRangeError);
+        }
+        Ok(AdcChannel {
+            adc: self,
+            channel,
+            reference_mv: reference_mv as i32,
+            resolution_bits,
+            calibration: Calibration::default(),
+        })
+    }
+
     /// Start continuous sampling of channel
     pub fn sample_continuous(&self, channel: usize) -> TockResult<()> {
         syscalls::command(DRIVER_NUMBER, command_nr::START_REPEAT, channel, 0)?;
@@ -131,6 +267,67 @@ impl<'a> Adc<'a> {
         Ok(())
     }
 
+    /// Starts continuous sampling of `channel` at `frequency` Hz into the
+    /// two buffers in `buffers`, alternating between them. Each filled buffer
+    /// is returned by the stream while the kernel fills the other one.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use futures::stream::StreamExt;
+    /// # use libtock::adc::AdcStreamBuffers;
+    /// # use libtock::result::TockResult;
+    /// # async fn doc() -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let adc_driver = drivers.adc.init_driver()?;
+    ///
+    /// let mut buffers = AdcStreamBuffers::<64>::default();
+    /// let mut stream = adc_driver.stream(0, 1000, &mut buffers)?;
+    /// while let Some(samples) = stream.next().await {
+    ///     let _average = samples.iter().map(|&sample| sample as usize).sum::<usize>() / 64;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stream<const N: usize>(
+        &self,
+        channel: usize,
+        frequency: usize,
+        buffers: &'a mut AdcStreamBuffers<N>,
+    ) -> TockResult<AdcStream<'a, N>> {
+        let AdcStreamBuffers {
+            samples: [buffer, alt_buffer],
+            state,
+            state_ref,
+        } = buffers;
+        // The kernel writes to the sample arrays while the stream reads them,
+        // so they are only accessed through the raw pointers of their cells.
+        let samples = [buffer.get() as *const _, alt_buffer.get() as *const _];
+        let state: &'a AdcStreamState = state;
+        state.alt_address.set(samples[1] as usize);
+        // The callback only receives the pointer in `state_ref` and reads
+        // `state` through a shared reference, like the stream.
+        *state_ref = state;
+        let buffer = syscalls::allow(DRIVER_NUMBER, allow_nr::BUFFER, unsafe {
+            as_bytes(&mut *buffer.get())
+        })?;
+        let alt_buffer = syscalls::allow(DRIVER_NUMBER, allow_nr::BUFFER_ALT, unsafe {
+            as_bytes(&mut *alt_buffer.get())
+        })?;
+        let subscription = syscalls::subscribe::<AdcBufferEventConsumer, _>(
+            DRIVER_NUMBER,
+            subscribe_nr::SUBSCRIBE_CALLBACK,
+            state_ref,
+        )?;
+        self.sample_continuous_buffered_alt(channel, frequency)?;
+        Ok(AdcStream {
+            _subscription: subscription,
+            _buffer: buffer,
+            _alt_buffer: alt_buffer,
+            samples,
+            state,
+        })
+    }
+
     /// Stop any started sampling operation
     pub fn stop(&self) -> TockResult<()> {
         syscalls::command(DRIVER_NUMBER, command_nr::STOP, 0, 0)?;
@@ -138,8 +335,402 @@ impl<'a> Adc<'a> {
     }
 }
 
+fn as_bytes(samples: &mut [u16]) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u8, samples.len() * 2) }
+}
+
+/// Storage for [Adc::stream()] holding two buffers of `N` samples each
+pub struct AdcStreamBuffers<const N: usize> {
+    samples: [UnsafeCell<[u16; N]>; 2],
+    state: AdcStreamState,
+    state_ref: *const AdcStreamState,
+}
+
+impl<const N: usize> Default for AdcStreamBuffers<N> {
+    fn default() -> Self {
+        AdcStreamBuffers {
+            samples: [UnsafeCell::new([0; N]), UnsafeCell::new([0; N])],
+            state: AdcStreamState::default(),
+            state_ref: ptr::null(),
+        }
+    }
+}
+
+/// Bookkeeping shared between the callback and the stream
+#[derive(Default)]
+struct AdcStreamState {
+    alt_address: Cell<usize>,
+    filled: Cell<Option<usize>>,
+    overrun_count: Cell<usize>,
+}
+
+impl AdcStreamState {
+    /// Marks the buffer at `address` as filled. If the previously filled
+    /// buffer has not been taken yet, it is lost.
+    fn mark_filled(&self, address: usize) {
+        let index = if address == self.alt_address.get() {
+            1
+        } else {
+            0
+        };
+        if self.filled.replace(Some(index)).is_some() {
+            self.overrun_count.set(self.overrun_count.get() + 1);
+        }
+    }
+}
+
+struct AdcBufferEventConsumer;
+
+impl Consumer<*const AdcStreamState> for AdcBufferEventConsumer {
+    fn consume(state: &mut *const AdcStreamState, _: usize, _: usize, address: usize) {
+        unsafe { &**state }.mark_filled(address);
+    }
+}
+
+/// Stream of sample buffers created by [Adc::stream()]. Sampling stops when
+/// the stream is dropped.
+pub struct AdcStream<'a, const N: usize> {
+    _subscription: CallbackSubscription<'a>,
+    _buffer: SharedMemory<'a>,
+    _alt_buffer: SharedMemory<'a>,
+    samples: [*const [u16; N]; 2],
+    state: &'a AdcStreamState,
+}
+
+impl<'a, const N: usize> AdcStream<'a, N> {
+    /// Number of filled buffers that were overwritten before they were
+    /// consumed
+    pub fn overrun_count(&self) -> usize {
+        self.state.overrun_count.get()
+    }
+
+    /// Copies the samples out of the last filled buffer
+    fn take_filled(&self) -> Option<[u16; N]> {
+        let index = self.state.filled.take()?;
+        let raw = self.samples[index] as *const u16;
+        let mut samples = [0; N];
+        for (offset, sample) in samples.iter_mut().enumerate() {
+            // The kernel writes the samples as little endian bytes
+            *sample = u16::from_le(unsafe { ptr::read_volatile(raw.add(offset)) });
+        }
+        Some(samples)
+    }
+}
+
+impl<'a, const N: usize> Stream for AdcStream<'a, N> {
+    type Item = [u16; N];
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.take_filled() {
+            Some(samples) => Poll::Ready(Some(samples)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<'a, const N: usize> Drop for AdcStream<'a, N> {
+    fn drop(&mut self) {
+        let _ = syscalls::command(DRIVER_NUMBER, command_nr::STOP, 0, 0);
+    }
+}
+
+/// Linear correction `gain * millivolts + offset` applied to converted
+/// samples, with the gain given as a fraction
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Calibration {
+    gain_numerator: i32,
+    gain_denominator: i32,
+    offset_mv: i32,
+}
+
+impl Default for Calibration {
+    /// The calibration that leaves samples unchanged
+    fn default() -> Self {
+        Calibration {
+            gain_numerator: 1,
+            gain_denominator: 1,
+            offset_mv: 0,
+        }
+    }
+}
+
+impl Calibration {
+    pub fn new(
+        gain_numerator: i32,
+        gain_denominator: i32,
+        offset_mv: i32,
+    ) -> Result<Calibration, OutOfRangeError> {
+        if gain_denominator == 0 {
+            return Err(OutOfRangeError);
+        }
+        Ok(Calibration {
+            gain_numerator,
+            gain_denominator,
+            offset_mv,
+        })
+    }
+
+    /// Derives the calibration from two reference points, each given as the
+    /// uncalibrated reading and the actual voltage in millivolts
+    pub fn from_two_points(
+        (measured1, actual1): (i32, i32),
+        (measured2, actual2): (i32, i32),
+    ) -> Result<Calibration, OutOfRangeError> {
+        let gain_numerator = actual2 - actual1;
+        let gain_denominator = measured2 - measured1;
+        if gain_denominator == 0 {
+            return Err(OutOfRangeError);
+        }
+        let offset_mv =
+            actual1 as i64 - measured1 as i64 * gain_numerator as i64 / gain_denominator as i64;
+        Calibration::new(gain_numerator, gain_denominator, offset_mv as i32)
+    }
+
+    pub fn apply(self, millivolts: i32) -> i32 {
+        let scaled = millivolts as i64 * self.gain_numerator as i64 / self.gain_denominator as i64;
+        (scaled + self.offset_mv as i64) as i32
+    }
+}
+
+/// A single ADC channel converting samples to millivolts.
+///
+/// Readings in other units are obtained by passing a transfer function to
+/// [read_as()](Self::read_as), which receives the calibrated voltage.
+///
+/// Example usage (TMP36 temperature sensor, 10 mV/°C with 500 mV at 0 °C):
+/// ```no_run
+/// # use libtock::adc::Calibration;
+/// # use libtock::result::TockResult;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let adc_driver = drivers.adc.init_driver()?;
+/// let sensor = adc_driver
+///     .channel(0)?
+///     .with_calibration(Calibration::from_two_points((12, 0), (3290, 3300))?);
+/// let _millivolts = sensor.read_millivolts().await?;
+/// let _decidegrees = sensor.read_as(|millivolts| millivolts - 500).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AdcChannel<'a> {
+    adc: &'a Adc<'a>,
+    channel: usize,
+    reference_mv: i32,
+    resolution_bits: usize,
+    calibration: Calibration,
+}
+
+impl<'a> AdcChannel<'a> {
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    pub fn channel(&self) -> usize {
+        self.channel
+    }
+
+    pub fn reference_mv(&self) -> i32 {
+        self.reference_mv
+    }
+
+    pub fn resolution_bits(&self) -> usize {
+        self.resolution_bits
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    /// Converts a raw sample of this channel to calibrated millivolts
+    pub fn to_millivolts(&self, raw: usize) -> i32 {
+        let millivolts = (raw as i64 * self.reference_mv as i64) >> self.resolution_bits;
+        self.calibration.apply(millivolts as i32)
+    }
+
+    /// Samples the channel once and returns the raw value
+    pub async fn read_raw(&self) -> TockResult<usize> {
+        Ok(self.adc.read(self.channel).await?.value())
+    }
+
+    /// Samples the channel once and returns the calibrated voltage
+    pub async fn read_millivolts(&self) -> TockResult<i32> {
+        Ok(self.to_millivolts(self.read_raw().await?))
+    }
+
+    /// Samples the channel once and converts the calibrated voltage with
+    /// `transfer`, e.g. the divider equation and Steinhart-Hart equation of a
+    /// thermistor
+    pub async fn read_as<T, F: FnOnce(i32) -> T>(&self, transfer: F) -> TockResult<T> {
+        Ok(transfer(self.read_millivolts().await?))
+    }
+}
+
 impl<'a> Drop for Adc<'a> {
     fn drop(&mut self) {
         let _ = self.stop();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor;
+    use crate::syscalls::raw::Event;
+    use core::task::Poll;
+
+    #[test]
+    pub fn read_subscribes_and_starts_sample() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(2);
+            let adc_driver = drivers.adc.init_driver()?;
+            next_return.set(0);
+
+            let out_of_range = adc_driver.read(2);
+            ::futures::pin_mut!(out_of_range);
+            match executor::poll(out_of_range) {
+                Poll::Ready(Err(_)) => {}
+                _ => panic!("Channel 2 should not exist"),
+            }
+
+            let read = adc_driver.read(1);
+            ::futures::pin_mut!(read);
+            assert!(executor::poll(read).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            events[0],
+            Event::Command(DRIVER_NUMBER, command_nr::COUNT, 0, 0)
+        );
+        match events[1] {
+            Event::Subscribe(DRIVER_NUMBER, subscribe_nr::SUBSCRIBE_CALLBACK, _, _) => {}
+            _ => panic!("Unexpected events: {:?}", events),
+        }
+        assert_eq!(
+            events[2],
+            Event::Command(DRIVER_NUMBER, command_nr::START, 1, 0)
+        );
+    }
+
+    #[test]
+    pub fn filled_buffers_are_decoded_and_overruns_counted() {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(1);
+            let adc_driver = drivers.adc.init_driver()?;
+            next_return.set(0);
+            let mut buffers = AdcStreamBuffers::<3>::default();
+            buffers.samples = [UnsafeCell::new([1, 2, 3]), UnsafeCell::new([4, 5, 6])];
+            let stream = adc_driver.stream(0, 500, &mut buffers)?;
+            let [address, alt_address] = [stream.samples[0] as usize, stream.samples[1] as usize];
+            let mut state_ref: *const AdcStreamState = stream.state;
+            assert_eq!(stream.take_filled(), None);
+
+            AdcBufferEventConsumer::consume(&mut state_ref, 3, 3 << 8, alt_address);
+            assert_eq!(stream.take_filled(), Some([4, 5, 6]));
+            assert_eq!(stream.take_filled(), None);
+
+            AdcBufferEventConsumer::consume(&mut state_ref, 3, 3 << 8, address);
+            AdcBufferEventConsumer::consume(&mut state_ref, 3, 3 << 8, alt_address);
+            assert_eq!(stream.overrun_count(), 1);
+            assert_eq!(stream.take_filled(), Some([4, 5, 6]));
+            mem::drop(stream);
+            mem::forget(adc_driver);
+            Ok(())
+        });
+    }
+
+    #[test]
+    pub fn stream_shares_both_buffers_and_stops_on_drop() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(1);
+            let adc_driver = drivers.adc.init_driver()?;
+            next_return.set(0);
+            let mut buffers = AdcStreamBuffers::<16>::default();
+            let stream = adc_driver.stream(0, 500, &mut buffers)?;
+            assert_eq!(stream.overrun_count(), 0);
+            mem::drop(stream);
+            mem::forget(adc_driver);
+            Ok(())
+        });
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| match *event {
+                Event::Allow(_, allow_number, _, len) => (0, allow_number, len),
+                Event::Subscribe(_, subscribe_number, _, _) => (1, subscribe_number, 0),
+                Event::Command(_, command_number, arg1, arg2) => (2 + command_number, arg1, arg2),
+                _ => (9, 0, 0),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2 + command_nr::COUNT, 0, 0),
+                (0, allow_nr::BUFFER, 32),
+                (0, allow_nr::BUFFER_ALT, 32),
+                (1, subscribe_nr::SUBSCRIBE_CALLBACK, 0),
+                (2 + command_nr::START_REPEAT_BUFFER_ALT, 0, 500),
+                (2 + command_nr::STOP, 0, 0),
+                (1, subscribe_nr::SUBSCRIBE_CALLBACK, 0),
+                (0, allow_nr::BUFFER, 0),
+                (0, allow_nr::BUFFER_ALT, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn calibration_is_applied_to_millivolts() {
+        let calibration = Calibration::from_two_points((100, 90), (3100, 3090))
+            .ok()
+            .unwrap();
+        assert_eq!(calibration.apply(100), 90);
+        assert_eq!(calibration.apply(2000), 1990);
+        assert_eq!(Calibration::new(9, 10, 5).ok().unwrap().apply(1000), 905);
+        assert!(Calibration::from_two_points((5, 0), (5, 10)).is_err());
+
+        let adc = Adc {
+            num_channels: 2,
+            lifetime: PhantomData,
+        };
+        let channel = adc.channel_with_reference(1, 3300, 12).ok().unwrap();
+        assert_eq!(channel.to_millivolts(0), 0);
+        assert_eq!(channel.to_millivolts(2048), 1650);
+        let channel = channel.with_calibration(calibration);
+        assert_eq!(channel.to_millivolts(2048), 1640);
+        assert!(adc.channel_with_reference(2, 3300, 12).is_err());
+        assert!(adc.channel_with_reference(0, 3300, 0).is_err());
+        mem::forget(adc);
+    }
+
+    #[test]
+    pub fn channel_queries_reference_and_resolution() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(1);
+            let adc_driver = drivers.adc.init_driver()?;
+            next_return.set(10);
+            let channel = adc_driver.channel(0)?;
+            assert_eq!(channel.reference_mv(), 10);
+            assert_eq!(channel.resolution_bits(), 10);
+            mem::forget(adc_driver);
+            Ok(())
+        });
+        let commands: Vec<_> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Command(DRIVER_NUMBER, command, _, _) => Some(command),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                command_nr::COUNT,
+                command_nr::GET_REFERENCE_VOLTAGE,
+                command_nr::GET_RESOLUTION_BITS,
+            ]
+        );
+    }
+}
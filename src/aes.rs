@@ -0,0 +1,551 @@
+use crate::crypto;
+use crate::crypto::ZeroOnDrop;
+use crate::result::OtherError;
+use crate::result::TockResult;
+use crate::result::SUCCESS;
+use crate::syscalls;
+use core::marker::PhantomData;
+
+const DRIVER_NUMBER: usize = 0x40006;
+
+pub const KEY_SIZE: usize = 16;
+pub const BLOCK_SIZE: usize = 16;
+pub const DATA_BUFFER_SIZE: usize = 256;
+pub const MAX_TAG_SIZE: usize = 16;
+
+mod command_nr {
+    pub const IS_DRIVER_AVAILABLE: usize = 0;
+    pub const SET_ALGORITHM: usize = 1;
+    pub const RUN: usize = 2;
+}
+
+mod allow_nr {
+    pub const KEY: usize = 0;
+    pub const IV: usize = 1;
+    pub const SOURCE: usize = 2;
+    pub const DEST: usize = 3;
+}
+
+/// Block cipher mode of operation of AES-128
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AesMode {
+    /// Electronic codebook, only suitable for single blocks of random data
+    Ecb,
+    Cbc {
+        iv: [u8; BLOCK_SIZE],
+    },
+    Ctr {
+        counter: [u8; BLOCK_SIZE],
+    },
+}
+
+impl AesMode {
+    fn id(self) -> usize {
+        match self {
+            AesMode::Ctr { .. } => 0,
+            AesMode::Cbc { .. } => 1,
+            AesMode::Ecb => 2,
+        }
+    }
+
+    fn iv(&self) -> Option<&[u8]> {
+        match self {
+            AesMode::Ecb => None,
+            AesMode::Cbc { iv } => Some(iv),
+            AesMode::Ctr { counter } => Some(counter),
+        }
+    }
+
+    /// Whether the data has to consist of whole blocks
+    fn is_block_aligned(self) -> bool {
+        match self {
+            AesMode::Ecb | AesMode::Cbc { .. } => true,
+            AesMode::Ctr { .. } => false,
+        }
+    }
+}
+
+/// Authenticated encryption mode of AES-128. Kernels without support for a
+/// mode reject it with a [CommandError](crate::result::CommandError).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AeadMode {
+    /// Counter with CBC-MAC, with a tag of 4, 6, 8, 10, 12, 14 or 16 bytes
+    Ccm { nonce: [u8; 13], tag_len: usize },
+    /// Galois/counter mode with a tag of 16 bytes
+    Gcm { iv: [u8; 12] },
+}
+
+impl AeadMode {
+    fn id(self) -> usize {
+        match self {
+            AeadMode::Ccm { .. } => 3,
+            AeadMode::Gcm { .. } => 4,
+        }
+    }
+
+    fn iv(&self) -> &[u8] {
+        match self {
+            AeadMode::Ccm { nonce, .. } => nonce,
+            AeadMode::Gcm { iv } => iv,
+        }
+    }
+
+    pub fn tag_len(self) -> usize {
+        match self {
+            AeadMode::Ccm { tag_len, .. } => tag_len,
+            AeadMode::Gcm { .. } => MAX_TAG_SIZE,
+        }
+    }
+}
+
+#[non_exhaustive]
+pub struct AesDriverFactory;
+
+impl AesDriverFactory {
+    pub fn init_driver(&mut self) -> TockResult<AesDriver> {
+        syscalls::command(DRIVER_NUMBER, command_nr::IS_DRIVER_AVAILABLE, 0, 0)?;
+        let aes = AesDriver {
+            lifetime: PhantomData,
+        };
+        Ok(aes)
+    }
+}
+
+pub struct AesDriver<'a> {
+    lifetime: PhantomData<&'a ()>,
+}
+
+impl<'a> AesDriver<'a> {
+    /// Encrypts `input` into `output`, which must have the same length of at
+    /// most [DATA_BUFFER_SIZE] bytes. ECB and CBC require a multiple of
+    /// [BLOCK_SIZE] bytes.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use libtock::aes::AesMode;
+    /// # use libtock::result::TockResult;
+    /// # async fn doc(key: &[u8; 16], iv: [u8; 16]) -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let aes_driver = drivers.aes.init_driver()?;
+    /// let mut ciphertext = [0; 32];
+    /// aes_driver
+    ///     .encrypt(key, AesMode::Cbc { iv }, &[42; 32], &mut ciphertext)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn encrypt(
+        &self,
+        key: &[u8; KEY_SIZE],
+        mode: AesMode,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> TockResult<()> {
+        self.crypt(true, key, mode, input, output).await
+    }
+
+    /// Decrypts `input` into `output` with the same restrictions as
+    /// [encrypt()](Self::encrypt)
+    pub async fn decrypt(
+        &self,
+        key: &[u8; KEY_SIZE],
+        mode: AesMode,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> TockResult<()> {
+        self.crypt(false, key, mode, input, output).await
+    }
+
+    /// Encrypts `plaintext` into `ciphertext` of the same length and
+    /// authenticates it together with `aad`, which is not encrypted. `tag`
+    /// must have the tag length of `mode`. `aad` and `plaintext` may be at
+    /// most [DATA_BUFFER_SIZE] bytes long in total.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use libtock::aes::AeadMode;
+    /// # use libtock::result::TockResult;
+    /// # async fn doc(key: &[u8; 16], iv: [u8; 12]) -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let aes_driver = drivers.aes.init_driver()?;
+    /// let mode = AeadMode::Gcm { iv };
+    /// let reading = [0x12, 0x34];
+    /// let mut payload = [0; 2];
+    /// let mut tag = [0; 16];
+    /// aes_driver
+    ///     .encrypt_aead(key, mode, b"sensor 1", &reading, &mut payload, &mut tag)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn encrypt_aead(
+        &self,
+        key: &[u8; KEY_SIZE],
+        mode: AeadMode,
+        aad: &[u8],
+        plaintext: &[u8],
+        ciphertext: &mut [u8],
+        tag: &mut [u8],
+    ) -> TockResult<()> {
+        validate_aead(mode, aad, plaintext, ciphertext, tag)?;
+        let mut source = [0; DATA_BUFFER_SIZE];
+        let mut source = ZeroOnDrop(&mut source[..aad.len() + plaintext.len()]);
+        source[..aad.len()].copy_from_slice(aad);
+        source[aad.len()..].copy_from_slice(plaintext);
+        let mut dest = [0; DATA_BUFFER_SIZE + MAX_TAG_SIZE];
+        let mut dest = ZeroOnDrop(&mut dest[..plaintext.len() + tag.len()]);
+        self.execute(
+            mode.id(),
+            true,
+            key,
+            Some(mode.iv()),
+            &mut source,
+            &mut dest,
+            (aad.len(), tag.len()),
+        )
+        .await?;
+        ciphertext.copy_from_slice(&dest[..plaintext.len()]);
+        tag.copy_from_slice(&dest[plaintext.len()..]);
+        Ok(())
+    }
+
+    /// Verifies `tag` over `aad` and `ciphertext` and decrypts the latter
+    /// into `plaintext`. Fails with [OtherError::AesAuthenticationFailed] if
+    /// the data has been tampered with, in which case `plaintext` is left
+    /// untouched.
+    pub async fn decrypt_aead(
+        &self,
+        key: &[u8; KEY_SIZE],
+        mode: AeadMode,
+        aad: &[u8],
+        ciphertext: &[u8],
+        tag: &[u8],
+        plaintext: &mut [u8],
+    ) -> TockResult<()> {
+        validate_aead(mode, aad, ciphertext, plaintext, tag)?;
+        let mut source = [0; DATA_BUFFER_SIZE + MAX_TAG_SIZE];
+        let mut source = ZeroOnDrop(&mut source[..aad.len() + ciphertext.len() + tag.len()]);
+        let (source_aad, source_data) = source.split_at_mut(aad.len());
+        source_aad.copy_from_slice(aad);
+        source_data[..ciphertext.len()].copy_from_slice(ciphertext);
+        source_data[ciphertext.len()..].copy_from_slice(tag);
+        let mut dest = [0; DATA_BUFFER_SIZE];
+        let mut dest = ZeroOnDrop(&mut dest[..ciphertext.len()]);
+        let tag_valid = self
+            .execute(
+                mode.id(),
+                false,
+                key,
+                Some(mode.iv()),
+                &mut source,
+                &mut dest,
+                (aad.len(), tag.len()),
+            )
+            .await?;
+        if !tag_valid {
+            return Err(OtherError::AesAuthenticationFailed.into());
+        }
+        plaintext.copy_from_slice(&dest);
+        Ok(())
+    }
+
+    async fn crypt(
+        &self,
+        encrypting: bool,
+        key: &[u8; KEY_SIZE],
+        mode: AesMode,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> TockResult<()> {
+        if input.len() != output.len() || mode.is_block_aligned() && input.len() % BLOCK_SIZE != 0 {
+            return Err(OtherError::AesInvalidLength.into());
+        }
+        if input.len() > DATA_BUFFER_SIZE {
+            return Err(OtherError::AesDataTooLong.into());
+        }
+        let mut source = [0; DATA_BUFFER_SIZE];
+        let mut source = ZeroOnDrop(&mut source[..input.len()]);
+        source.copy_from_slice(input);
+        let mut dest = [0; DATA_BUFFER_SIZE];
+        let mut dest = ZeroOnDrop(&mut dest[..output.len()]);
+        self.execute(
+            mode.id(),
+            encrypting,
+            key,
+            mode.iv(),
+            &mut source,
+            &mut dest,
+            (0, 0),
+        )
+        .await?;
+        output.copy_from_slice(&dest);
+        Ok(())
+    }
+
+    /// Shares the buffers with the kernel, runs the operation and waits for
+    /// its completion. Returns whether the kernel reported a valid tag. The
+    /// copy of the key is zeroed before returning, as are the copies of the
+    /// data made by the callers.
+    #[allow(clippy::too_many_arguments)]
+    async fn execute(
+        &self,
+        mode_id: usize,
+        encrypting: bool,
+        key: &[u8; KEY_SIZE],
+        iv: Option<&[u8]>,
+        source: &mut [u8],
+        dest: &mut [u8],
+        (aad_len, tag_len): (usize, usize),
+    ) -> TockResult<bool> {
+        let mut key_buffer = *key;
+        let mut key_buffer = ZeroOnDrop(&mut key_buffer);
+        let mut iv_buffer = [0; BLOCK_SIZE];
+        let iv_buffer = iv.map(|iv| {
+            let iv_buffer = &mut iv_buffer[..iv.len()];
+            iv_buffer.copy_from_slice(iv);
+            iv_buffer
+        });
+        let (result_code, tag_valid) = crypto::run(
+            DRIVER_NUMBER,
+            &mut [
+                (allow_nr::KEY, Some(&mut *key_buffer)),
+                (allow_nr::IV, iv_buffer),
+                (allow_nr::SOURCE, Some(source)),
+                (allow_nr::DEST, Some(dest)),
+            ],
+            &[
+                (command_nr::SET_ALGORITHM, mode_id, encrypting as usize),
+                (command_nr::RUN, aad_len, tag_len),
+            ],
+        )
+        .await?;
+        match result_code {
+            SUCCESS => Ok(tag_valid != 0),
+            _ => Err(OtherError::AesFailed.into()),
+        }
+    }
+}
+
+fn validate_aead(
+    mode: AeadMode,
+    aad: &[u8],
+    input: &[u8],
+    output: &[u8],
+    tag: &[u8],
+) -> TockResult<()> {
+    let valid_tag_len = match mode {
+        AeadMode::Ccm { tag_len, .. } => tag_len >= 4 && tag_len <= 16 && tag_len % 2 == 0,
+        AeadMode::Gcm { .. } => true,
+    };
+    if !valid_tag_len || tag.len() != mode.tag_len() || input.len() != output.len() {
+        return Err(OtherError::AesInvalidLength.into());
+    }
+    if aad.len() + input.len() > DATA_BUFFER_SIZE {
+        return Err(OtherError::AesDataTooLong.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor;
+    use crate::result::TockError;
+    use crate::syscalls::raw::Event;
+    use core::mem;
+    use core::task::Poll;
+
+    #[test]
+    pub fn block_modes_share_key_iv_and_data() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let aes_driver = drivers.aes.init_driver()?;
+            let mut output = [0; 32];
+            let encrypt = aes_driver.encrypt(
+                &[1; KEY_SIZE],
+                AesMode::Cbc {
+                    iv: [2; BLOCK_SIZE],
+                },
+                &[3; 32],
+                &mut output,
+            );
+            ::futures::pin_mut!(encrypt);
+            assert!(executor::poll(encrypt).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            crypto::summarize(DRIVER_NUMBER, events)[..8],
+            [
+                (2, command_nr::IS_DRIVER_AVAILABLE, 0, 0),
+                (0, allow_nr::KEY, KEY_SIZE, 0),
+                (0, allow_nr::IV, BLOCK_SIZE, 0),
+                (0, allow_nr::SOURCE, 32, 0),
+                (0, allow_nr::DEST, 32, 0),
+                (1, crypto::SUBSCRIBE_CALLBACK, 0, 0),
+                (2, command_nr::SET_ALGORITHM, 1, 1),
+                (2, command_nr::RUN, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn lengths_are_validated() {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let aes_driver = drivers.aes.init_driver()?;
+            let key = [0; KEY_SIZE];
+
+            let mut output = [0; 15];
+            let partial_block = aes_driver.decrypt(&key, AesMode::Ecb, &[0; 15], &mut output);
+            ::futures::pin_mut!(partial_block);
+            match executor::poll(partial_block) {
+                Poll::Ready(Err(TockError::Other(OtherError::AesInvalidLength))) => {}
+                _ => panic!("Partial block should be rejected"),
+            }
+
+            let mut output = [0; DATA_BUFFER_SIZE + 1];
+            let too_long = aes_driver.encrypt(
+                &key,
+                AesMode::Ctr { counter: [0; 16] },
+                &[0; DATA_BUFFER_SIZE + 1],
+                &mut output,
+            );
+            ::futures::pin_mut!(too_long);
+            match executor::poll(too_long) {
+                Poll::Ready(Err(TockError::Other(OtherError::AesDataTooLong))) => {}
+                _ => panic!("Data should be rejected"),
+            }
+
+            let mode = AeadMode::Ccm {
+                nonce: [0; 13],
+                tag_len: 5,
+            };
+            assert!(validate_aead(mode, &[], &[0; 4], &[0; 4], &[0; 5]).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    pub fn aead_appends_tag_to_output() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let aes_driver = drivers.aes.init_driver()?;
+            let (mut ciphertext, mut tag) = ([0; 5], [0; 16]);
+            let encrypt = aes_driver.encrypt_aead(
+                &[0; KEY_SIZE],
+                AeadMode::Gcm { iv: [0; 12] },
+                b"header",
+                b"hello",
+                &mut ciphertext,
+                &mut tag,
+            );
+            ::futures::pin_mut!(encrypt);
+            assert!(executor::poll(encrypt).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            crypto::summarize(DRIVER_NUMBER, events)[1..],
+            [
+                (0, allow_nr::KEY, KEY_SIZE, 0),
+                (0, allow_nr::IV, 12, 0),
+                (0, allow_nr::SOURCE, 11, 0),
+                (0, allow_nr::DEST, 21, 0),
+                (1, crypto::SUBSCRIBE_CALLBACK, 0, 0),
+                (2, command_nr::SET_ALGORITHM, 4, 1),
+                (2, command_nr::RUN, 6, 16),
+                (1, crypto::SUBSCRIBE_CALLBACK, 0, 0),
+                (0, allow_nr::DEST, 0, 0),
+                (0, allow_nr::SOURCE, 0, 0),
+                (0, allow_nr::IV, 0, 0),
+                (0, allow_nr::KEY, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn ccm_with_valid_tag_length_is_started() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let aes_driver = drivers.aes.init_driver()?;
+            let (mut ciphertext, mut tag) = ([0; 4], [0; 8]);
+            let encrypt = aes_driver.encrypt_aead(
+                &[0; KEY_SIZE],
+                AeadMode::Ccm {
+                    nonce: [0; 13],
+                    tag_len: 8,
+                },
+                b"id",
+                b"data",
+                &mut ciphertext,
+                &mut tag,
+            );
+            ::futures::pin_mut!(encrypt);
+            assert!(executor::poll(encrypt).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            crypto::summarize(DRIVER_NUMBER, events)[1..8],
+            [
+                (0, allow_nr::KEY, KEY_SIZE, 0),
+                (0, allow_nr::IV, 13, 0),
+                (0, allow_nr::SOURCE, 6, 0),
+                (0, allow_nr::DEST, 12, 0),
+                (1, crypto::SUBSCRIBE_CALLBACK, 0, 0),
+                (2, command_nr::SET_ALGORITHM, 3, 1),
+                (2, command_nr::RUN, 2, 8),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn invalid_tag_fails_authentication() {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let aes_driver = drivers.aes.init_driver()?;
+            let mut plaintext = [7; 5];
+            {
+                let decrypt = aes_driver.decrypt_aead(
+                    &[0; KEY_SIZE],
+                    AeadMode::Gcm { iv: [0; 12] },
+                    b"header",
+                    &[0; 5],
+                    &[0; 16],
+                    &mut plaintext,
+                );
+                ::futures::pin_mut!(decrypt);
+                let events = syscalls::raw::run_recording_events(|_| {
+                    assert!(executor::poll(decrypt.as_mut()).is_pending())
+                });
+                let (callback, userdata) = events
+                    .into_iter()
+                    .find_map(|event| match event {
+                        Event::Subscribe(
+                            DRIVER_NUMBER,
+                            crypto::SUBSCRIBE_CALLBACK,
+                            callback,
+                            data,
+                        ) if !callback.is_null() => Some((callback, data)),
+                        _ => None,
+                    })
+                    .unwrap();
+                // Completed successfully, but with `tag_valid == 0`
+                unsafe {
+                    let callback: extern "C" fn(usize, usize, usize, usize) =
+                        mem::transmute(callback);
+                    callback(0, 0, 0, userdata);
+                }
+                match executor::poll(decrypt) {
+                    Poll::Ready(Err(TockError::Other(OtherError::AesAuthenticationFailed))) => {}
+                    _ => panic!("Invalid tag should be rejected"),
+                }
+            }
+            assert_eq!(plaintext, [7; 5]);
+            Ok(())
+        });
+    }
+}
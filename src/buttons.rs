@@ -1,10 +1,16 @@
 use crate::callback::CallbackSubscription;
 use crate::callback::Consumer;
+use crate::event_queue::EventQueue;
 use crate::result::OtherError;
 use crate::result::OutOfRangeError;
 use crate::result::TockResult;
 use crate::syscalls;
 use core::marker::PhantomData;
+use core::pin::Pin;
+use core::ptr;
+use core::task::Context;
+use core::task::Poll;
+use futures::stream::Stream;
 
 const DRIVER_NUMBER: usize = 0x00003;
 
@@ -73,18 +79,127 @@ impl<'a> ButtonsDriver<'a> {
         )
         .map_err(Into::into)
     }
+
+    /// Returns a stream of the button events, which are queued in `buffer`
+    /// as long as the stream exists. Interrupts still have to be enabled for
+    /// each button of interest.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use futures::stream::StreamExt;
+    /// # use libtock::buttons::ButtonEventBuffer;
+    /// # use libtock::result::TockResult;
+    /// # async fn doc() -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let buttons_driver = drivers.buttons.init_driver()?;
+    ///
+    /// let mut buffer = ButtonEventBuffer::default();
+    /// let mut events = buttons_driver.events(&mut buffer)?;
+    /// for button in buttons_driver.buttons() {
+    ///     button.enable_interrupt()?;
+    /// }
+    ///
+    /// while let Some((button_num, state)) = events.next().await {
+    ///     // Handle event
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn events(&self, buffer: &'a mut ButtonEventBuffer) -> TockResult<ButtonEvents<'a>> {
+        let ButtonEventBuffer { queue, queue_ref } = buffer;
+        let queue: &'a ButtonEventQueue = queue;
+        // The callback only receives the pointer in `queue_ref` and pushes
+        // through a shared reference, while the stream pops.
+        *queue_ref = queue;
+        let subscription = syscalls::subscribe::<ButtonEventQueueConsumer, _>(
+            DRIVER_NUMBER,
+            subscribe_nr::SUBSCRIBE_CALLBACK,
+            queue_ref,
+        )?;
+        Ok(ButtonEvents {
+            _subscription: subscription,
+            queue,
+        })
+    }
 }
 
 struct ButtonsEventConsumer;
 
 impl<CB: Fn(usize, ButtonState)> Consumer<CB> for ButtonsEventConsumer {
     fn consume(callback: &mut CB, button_num: usize, button_state: usize, _: usize) {
-        let button_state = match button_state {
-            0 => ButtonState::Released,
-            1 => ButtonState::Pressed,
-            _ => return,
-        };
-        callback(button_num, button_state);
+        if let Some(button_state) = button_state_from_raw(button_state) {
+            callback(button_num, button_state);
+        }
+    }
+}
+
+struct ButtonEventQueueConsumer;
+
+impl Consumer<*const ButtonEventQueue> for ButtonEventQueueConsumer {
+    fn consume(
+        queue: &mut *const ButtonEventQueue,
+        button_num: usize,
+        button_state: usize,
+        _: usize,
+    ) {
+        if let Some(button_state) = button_state_from_raw(button_state) {
+            unsafe { &**queue }.push((button_num, button_state));
+        }
+    }
+}
+
+fn button_state_from_raw(button_state: usize) -> Option<ButtonState> {
+    match button_state {
+        0 => Some(ButtonState::Released),
+        1 => Some(ButtonState::Pressed),
+        _ => None,
+    }
+}
+
+/// Queue for `(button_num, state)` events received from the kernel
+pub type ButtonEventQueue = EventQueue<(usize, ButtonState)>;
+
+/// Storage of a [ButtonEvents] stream, which has to outlive it
+pub struct ButtonEventBuffer {
+    queue: ButtonEventQueue,
+    queue_ref: *const ButtonEventQueue,
+}
+
+impl Default for ButtonEventBuffer {
+    fn default() -> Self {
+        ButtonEventBuffer {
+            queue: ButtonEventQueue::default(),
+            queue_ref: ptr::null(),
+        }
+    }
+}
+
+/// Stream of `(button_num, state)` events created by [ButtonsDriver::events()]
+pub struct ButtonEvents<'a> {
+    _subscription: CallbackSubscription<'a>,
+    queue: &'a ButtonEventQueue,
+}
+
+impl<'a> ButtonEvents<'a> {
+    /// Number of events currently waiting in the queue
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Number of events that were dropped because the queue was full
+    pub fn overflow_count(&self) -> usize {
+        self.queue.overflow_count()
+    }
+}
+
+impl<'a> Stream for ButtonEvents<'a> {
+    type Item = (usize, ButtonState);
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.queue.pop() {
+            Some(event) => Poll::Ready(Some(event)),
+            None => Poll::Pending,
+        }
     }
 }
 
@@ -165,3 +280,52 @@ impl<'a> Button<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls::raw::Event;
+
+    #[test]
+    pub fn events_are_queued_in_order() {
+        let queue = ButtonEventQueue::default();
+        let mut queue_ref: *const ButtonEventQueue = &queue;
+        ButtonEventQueueConsumer::consume(&mut queue_ref, 0, 1, 0);
+        ButtonEventQueueConsumer::consume(&mut queue_ref, 1, 0, 0);
+        ButtonEventQueueConsumer::consume(&mut queue_ref, 1, 7, 0);
+        assert_eq!(queue.pop(), Some((0, ButtonState::Pressed)));
+        assert_eq!(queue.pop(), Some((1, ButtonState::Released)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    pub fn events_subscribes_and_unsubscribes() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+
+            next_return.set(2);
+            let buttons_driver = drivers.buttons.init_driver()?;
+            next_return.set(0);
+
+            let mut buffer = ButtonEventBuffer::default();
+            let events = buttons_driver.events(&mut buffer)?;
+            assert_eq!(events.pending(), 0);
+            Ok(())
+        });
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            Event::Command(DRIVER_NUMBER, command_nr::COUNT, 0, 0)
+        );
+        match (&events[1], &events[2]) {
+            (Event::Subscribe(driver, nr, _, _), Event::Subscribe(_, _, callback, _)) => {
+                assert_eq!(
+                    (*driver, *nr),
+                    (DRIVER_NUMBER, subscribe_nr::SUBSCRIBE_CALLBACK)
+                );
+                assert!(callback.is_null());
+            }
+            _ => panic!("Unexpected events: {:?}", events),
+        }
+    }
+}
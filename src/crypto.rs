@@ -0,0 +1,142 @@
+//! Operations shared by the bindings of the kernel's crypto capsules
+
+use crate::callback::Consumer;
+use crate::futures;
+use crate::result::TockResult;
+use crate::shared_memory::SharedMemory;
+use crate::syscalls;
+use core::cell::Cell;
+use core::fmt;
+use core::mem;
+use core::ops::Deref;
+use core::ops::DerefMut;
+use core::ptr;
+use core::sync::atomic;
+use core::sync::atomic::Ordering;
+
+/// Largest number of buffers an operation shares with the kernel
+const MAX_BUFFERS: usize = 4;
+
+/// Subscribe number of the completion callback of all crypto capsules
+pub(crate) const SUBSCRIBE_CALLBACK: usize = 0;
+
+struct CompletionConsumer;
+
+impl<CB: FnMut(usize, usize)> Consumer<CB> for CompletionConsumer {
+    fn consume(callback: &mut CB, result: usize, arg: usize, _: usize) {
+        callback(result, arg);
+    }
+}
+
+/// Shares `buffers`, given as allow numbers and optional buffers, with the
+/// driver `driver_number`, issues `commands` in order and waits for the
+/// completion callback. Returns the result code and the second argument of
+/// the callback. The callback is unsubscribed and the buffers are unshared
+/// in reverse order before returning.
+pub(crate) async fn run(
+    driver_number: usize,
+    buffers: &mut [(usize, Option<&mut [u8]>)],
+    commands: &[(usize, usize, usize)],
+) -> TockResult<(isize, usize)> {
+    debug_assert!(buffers.len() <= MAX_BUFFERS);
+    let completion = Cell::new(None);
+    let mut callback = |result, arg| completion.set(Some((result as isize, arg)));
+    // Filled from the back, so the buffers are unshared in reverse order when
+    // the array is dropped, even if the operation is cancelled
+    let mut shared: [Option<SharedMemory>; MAX_BUFFERS] = [None, None, None, None];
+    for (slot, buffer) in shared.iter_mut().rev().zip(buffers.iter_mut()) {
+        if let (allow_number, Some(buffer)) = buffer {
+            *slot = Some(syscalls::allow(driver_number, *allow_number, buffer)?);
+        }
+    }
+    let subscription = syscalls::subscribe::<CompletionConsumer, _>(
+        driver_number,
+        SUBSCRIBE_CALLBACK,
+        &mut callback,
+    )?;
+    for &(command, arg1, arg2) in commands {
+        syscalls::command(driver_number, command, arg1, arg2)?;
+    }
+    let completion = futures::wait_for_value(|| completion.get()).await;
+    mem::drop(subscription);
+    mem::drop(shared);
+    Ok(completion)
+}
+
+/// Copy of secret data like a key or a plaintext, overwritten with zeros when
+/// dropped. The zeros are written with volatile writes, so they are not
+/// optimized away although the buffer is not read again.
+pub(crate) struct ZeroOnDrop<'a>(pub(crate) &'a mut [u8]);
+
+impl<'a> Deref for ZeroOnDrop<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0
+    }
+}
+
+impl<'a> DerefMut for ZeroOnDrop<'a> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.0
+    }
+}
+
+impl<'a> Drop for ZeroOnDrop<'a> {
+    fn drop(&mut self) {
+        for byte in self.0.iter_mut() {
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// Formats `bytes` as lowercase hex digits
+pub(crate) fn fmt_hex(bytes: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+/// Maps the events of `driver_number` to tuples for comparison in tests:
+/// `(0, allow_number, len, 0)`, `(1, subscribe_number, 0, 0)` and
+/// `(2, command_number, arg1, arg2)`
+#[cfg(test)]
+pub(crate) fn summarize(
+    driver_number: usize,
+    events: Vec<syscalls::raw::Event>,
+) -> Vec<(usize, usize, usize, usize)> {
+    use crate::syscalls::raw::Event;
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Allow(driver, allow_number, _, len) if driver == driver_number => {
+                (0, allow_number, len, 0)
+            }
+            Event::Subscribe(driver, subscribe_number, _, _) if driver == driver_number => {
+                (1, subscribe_number, 0, 0)
+            }
+            Event::Command(driver, command_number, arg1, arg2) if driver == driver_number => {
+                (2, command_number, arg1, arg2)
+            }
+            _ => panic!("Unexpected event: {:?}", event),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn secret_copies_are_zeroed_on_drop() {
+        let mut buffer = [1; 4];
+        {
+            let mut secret = ZeroOnDrop(&mut buffer[..3]);
+            secret[0] = 2;
+            assert_eq!(*secret, [2, 1, 1]);
+        }
+        assert_eq!(buffer, [0, 0, 0, 1]);
+    }
+}
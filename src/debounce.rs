@@ -0,0 +1,194 @@
+//! Time-based debouncing of digital inputs like buttons and GPIO pins.
+//!
+//! Mechanical contacts bounce for a few milliseconds after being pressed or
+//! released. [Debounced] samples an input periodically using the timer driver
+//! and only reports a new state after it has been stable for the configured
+//! settle time.
+
+use crate::buttons::Button;
+use crate::buttons::ButtonState;
+use crate::gpio::GpioRead;
+use crate::gpio::GpioState;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+
+/// A digital input which can be sampled, e.g. a [Button] or a [GpioRead].
+pub trait DigitalInput {
+    /// Returns `true` if the input is active, i.e. the button is pressed or
+    /// the pin is high.
+    fn is_active(&self) -> TockResult<bool>;
+}
+
+impl<'a> DigitalInput for Button<'a> {
+    fn is_active(&self) -> TockResult<bool> {
+        Ok(self.read()? == ButtonState::Pressed)
+    }
+}
+
+impl<'a> DigitalInput for GpioRead<'a> {
+    fn is_active(&self) -> TockResult<bool> {
+        Ok(self.read()? == GpioState::High)
+    }
+}
+
+impl<T: DigitalInput> DigitalInput for &T {
+    fn is_active(&self) -> TockResult<bool> {
+        (**self).is_active()
+    }
+}
+
+/// Debouncing state machine operating on raw samples and clock ticks.
+///
+/// A change of the raw input is only accepted once it has persisted for
+/// `settle_ticks`. Tick values are allowed to wrap around.
+#[derive(Copy, Clone, Debug)]
+pub struct Debouncer {
+    settle_ticks: usize,
+    state: bool,
+    candidate: bool,
+    candidate_since: usize,
+}
+
+impl Debouncer {
+    pub fn new(initial_state: bool, settle_ticks: usize) -> Debouncer {
+        Debouncer {
+            settle_ticks,
+            state: initial_state,
+            candidate: initial_state,
+            candidate_since: 0,
+        }
+    }
+
+    /// The current debounced state
+    pub fn state(&self) -> bool {
+        self.state
+    }
+
+    /// Feeds a raw sample taken at clock value `now`. Returns the new state
+    /// if the debounced state changed.
+    pub fn update(&mut self, sample: bool, now: usize) -> Option<bool> {
+        if sample != self.candidate {
+            self.candidate = sample;
+            self.candidate_since = now;
+        }
+        if self.candidate != self.state
+            && now.wrapping_sub(self.candidate_since) >= self.settle_ticks
+        {
+            self.state = self.candidate;
+            Some(self.state)
+        } else {
+            None
+        }
+    }
+}
+
+/// A [DigitalInput] that is sampled periodically and debounced.
+///
+/// Example usage (wait for a debounced button press):
+/// ```no_run
+/// # use libtock::debounce::Debounced;
+/// # use libtock::result::TockResult;
+/// # use libtock::timer::Duration;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let buttons_driver = drivers.buttons.init_driver()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+///
+/// let button = buttons_driver.get(0)?;
+/// let mut button = Debounced::new(button, &timer_driver, Duration::from_ms(20))?;
+/// while !button.wait_for_change().await? {}
+/// # Ok(())
+/// # }
+/// ```
+pub struct Debounced<'a, I> {
+    input: I,
+    timer: &'a ParallelSleepDriver<'a>,
+    debouncer: Debouncer,
+    sample_interval: Duration<usize>,
+}
+
+impl<'a, I: DigitalInput> Debounced<'a, I> {
+    /// Creates a debounced input. The input is sampled four times during
+    /// `settle_time`, but at most once per millisecond.
+    pub fn new(
+        input: I,
+        timer: &'a ParallelSleepDriver<'a>,
+        settle_time: Duration<usize>,
+    ) -> TockResult<Debounced<'a, I>> {
+        let freq = timer.get_current_clock()?.clock_frequency().hz();
+        let debouncer = Debouncer::new(
+            input.is_active()?,
+            timer::ms_to_ticks(settle_time.ms(), freq),
+        );
+        Ok(Debounced {
+            input,
+            timer,
+            debouncer,
+            sample_interval: Duration::from_ms((settle_time.ms() / 4).max(1)),
+        })
+    }
+
+    pub fn input(&self) -> &I {
+        &self.input
+    }
+
+    /// The current debounced state
+    pub fn is_active(&self) -> bool {
+        self.debouncer.state()
+    }
+
+    /// Samples the input once. Returns the new state if the debounced state
+    /// changed.
+    pub fn poll(&mut self) -> TockResult<Option<bool>> {
+        let sample = self.input.is_active()?;
+        let now = self.timer.get_current_clock()?.num_ticks() as usize;
+        Ok(self.debouncer.update(sample, now))
+    }
+
+    /// Waits until the debounced state changes and returns the new state.
+    pub async fn wait_for_change(&mut self) -> TockResult<bool> {
+        loop {
+            if let Some(state) = self.poll()? {
+                return Ok(state);
+            }
+            self.timer.sleep(self.sample_interval).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn bouncing_is_suppressed() {
+        let mut debouncer = Debouncer::new(false, 10);
+        assert_eq!(debouncer.update(true, 100), None);
+        assert_eq!(debouncer.update(false, 103), None);
+        assert_eq!(debouncer.update(true, 105), None);
+        assert_eq!(debouncer.update(true, 114), None);
+        assert_eq!(debouncer.update(true, 115), Some(true));
+        assert_eq!(debouncer.update(true, 130), None);
+        assert_eq!(debouncer.state(), true);
+    }
+
+    #[test]
+    pub fn short_glitch_is_ignored() {
+        let mut debouncer = Debouncer::new(true, 10);
+        assert_eq!(debouncer.update(false, 0), None);
+        assert_eq!(debouncer.update(true, 5), None);
+        assert_eq!(debouncer.update(true, 50), None);
+        assert_eq!(debouncer.state(), true);
+    }
+
+    #[test]
+    pub fn clock_wrap_is_handled() {
+        let mut debouncer = Debouncer::new(false, 10);
+        assert_eq!(debouncer.update(true, core::usize::MAX - 4), None);
+        assert_eq!(debouncer.update(true, 4), None);
+        assert_eq!(debouncer.update(true, 5), Some(true));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::adc::AdcDriverFactory;
+use crate::aes::AesDriverFactory;
 use crate::buttons::ButtonsDriverFactory;
 use crate::console::ConsoleDriver;
 use crate::gpio::GpioDriverFactory;
@@ -11,6 +12,7 @@ use crate::sensors::ninedof::NinedofDriver;
 use crate::sensors::AmbientLightSensor;
 use crate::sensors::HumiditySensor;
 use crate::sensors::TemperatureSensor;
+use crate::sha::ShaDriverFactory;
 use crate::simple_ble::BleAdvertisingDriverFactory;
 use crate::simple_ble::BleScanningDriverFactory;
 use crate::temperature::TemperatureDriverFactory;
@@ -25,6 +27,8 @@ pub struct Drivers {
     pub timer: DriverContext,
     pub gpio: GpioDriverFactory,
     pub hmac: HmacDriverFactory,
+    pub sha: ShaDriverFactory,
+    pub aes: AesDriverFactory,
     pub temperature: TemperatureDriverFactory,
     pub buttons: ButtonsDriverFactory,
     pub adc: AdcDriverFactory,
@@ -64,6 +68,7 @@ pub unsafe fn retrieve_drivers_unsafe() -> Drivers {
 #[allow(clippy::declare_interior_mutable_const)]
 const DRIVERS: Drivers = Drivers {
     adc: AdcDriverFactory,
+    aes: AesDriverFactory,
     ble_advertising: BleAdvertisingDriverFactory,
     ble_scanning: BleScanningDriverFactory,
     buttons: ButtonsDriverFactory,
@@ -74,6 +79,7 @@ const DRIVERS: Drivers = Drivers {
     },
     gpio: GpioDriverFactory,
     hmac: HmacDriverFactory,
+    sha: ShaDriverFactory,
     temperature: TemperatureDriverFactory,
     rng: RngDriver,
     ambient_light_sensor: AmbientLightSensor,
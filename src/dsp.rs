@@ -0,0 +1,548 @@
+//! Fixed-point signal processing for sampled data like ADC or ninedof
+//! readings.
+//!
+//! All filters operate on `i32` samples and implement [Filter], so they can
+//! be applied to single values, to the buffers of an
+//! [AdcStream](crate::adc::AdcStream) or to the items of any stream via
+//! `StreamExt::map`. Coefficients are given as fractions of [ONE].
+//!
+//! Example usage (band-pass filtered vibration signal):
+//! ```no_run
+//! # use futures::stream::StreamExt;
+//! # use libtock::adc::AdcStreamBuffers;
+//! # use libtock::dsp::{Filter, HighPass, LowPass, PeakDetector};
+//! # use libtock::result::TockResult;
+//! # async fn doc() -> TockResult<()> {
+//! let mut drivers = libtock::retrieve_drivers()?;
+//! let adc_driver = drivers.adc.init_driver()?;
+//! let mut buffers = AdcStreamBuffers::<64>::default();
+//! let mut stream = adc_driver.stream(0, 1000, &mut buffers)?;
+//!
+//! let mut filter = (HighPass::new(5, 1000)?, LowPass::new(100, 1000)?);
+//! let mut peaks = PeakDetector::new(200);
+//! let mut filtered = [0; 64];
+//! while let Some(samples) = stream.next().await {
+//!     filter.process_slice(&samples, &mut filtered);
+//!     for &sample in filtered.iter() {
+//!         if let Some(_peak) = peaks.process(sample) {
+//!             // Handle peak
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::result::OutOfRangeError;
+
+/// Number of fractional bits of coefficients
+pub const FRACTIONAL_BITS: u32 = 16;
+
+/// The coefficient 1.0
+pub const ONE: i32 = 1 << FRACTIONAL_BITS;
+
+/// 2π as a fraction of 1000
+const TWO_PI_MILLI: u64 = 6283;
+
+/// A filter transforming a sequence of samples
+pub trait Filter {
+    /// Feeds `sample` into the filter and returns the filtered value
+    fn process(&mut self, sample: i32) -> i32;
+
+    /// Forgets all previous samples
+    fn reset(&mut self);
+
+    /// Filters `input` into `output`, stopping at the end of the shorter one
+    fn process_slice<T: Copy + Into<i32>>(&mut self, input: &[T], output: &mut [i32]) {
+        for (sample, filtered) in input.iter().zip(output.iter_mut()) {
+            *filtered = self.process((*sample).into());
+        }
+    }
+}
+
+impl<F: Filter> Filter for &mut F {
+    fn process(&mut self, sample: i32) -> i32 {
+        (**self).process(sample)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+/// Two filters applied one after another
+impl<A: Filter, B: Filter> Filter for (A, B) {
+    fn process(&mut self, sample: i32) -> i32 {
+        self.1.process(self.0.process(sample))
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+        self.1.reset();
+    }
+}
+
+/// Ring buffer over the last `N` samples, providing their statistics
+pub struct Window<const N: usize> {
+    samples: [i32; N],
+    next: usize,
+    len: usize,
+}
+
+impl<const N: usize> Default for Window<N> {
+    fn default() -> Self {
+        Window {
+            samples: [0; N],
+            next: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<const N: usize> Window<N> {
+    /// Adds `sample`, replacing the oldest one if the window is full.
+    /// Returns the replaced sample.
+    pub fn push(&mut self, sample: i32) -> Option<i32> {
+        if N == 0 {
+            return None;
+        }
+        let replaced = self.samples[self.next];
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % N;
+        if self.len == N {
+            Some(replaced)
+        } else {
+            self.len += 1;
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// The samples in the window in no particular order
+    pub fn samples(&self) -> &[i32] {
+        &self.samples[..self.len]
+    }
+
+    pub fn min(&self) -> Option<i32> {
+        self.samples().iter().copied().min()
+    }
+
+    pub fn max(&self) -> Option<i32> {
+        self.samples().iter().copied().max()
+    }
+
+    /// Difference between the largest and the smallest sample
+    pub fn peak_to_peak(&self) -> Option<i32> {
+        Some(self.max()? - self.min()?)
+    }
+
+    pub fn mean(&self) -> Option<i32> {
+        if self.is_empty() {
+            return None;
+        }
+        let sum: i64 = self.samples().iter().map(|&sample| sample as i64).sum();
+        Some((sum / self.len as i64) as i32)
+    }
+
+    /// Root mean square of the samples, rounded down. Saturates at
+    /// `i32::MAX`, which only the RMS of `i32::MIN` samples exceeds.
+    pub fn rms(&self) -> Option<i32> {
+        if self.is_empty() {
+            return None;
+        }
+        // Squares of full range samples overflow a u64 sum after 4 samples
+        let sum_of_squares: u128 = self
+            .samples()
+            .iter()
+            .map(|&sample| (sample as i64 * sample as i64) as u128)
+            .sum();
+        let rms = isqrt((sum_of_squares / self.len as u128) as u64);
+        Some(rms.min(i32::max_value() as u64) as i32)
+    }
+}
+
+/// Mean of the last `N` samples
+#[derive(Default)]
+pub struct MovingAverage<const N: usize> {
+    window: Window<N>,
+    sum: i64,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    pub fn window(&self) -> &Window<N> {
+        &self.window
+    }
+}
+
+impl<const N: usize> Filter for MovingAverage<N> {
+    /// Until `N` samples have been processed, the mean of all samples so far
+    /// is returned.
+    fn process(&mut self, sample: i32) -> i32 {
+        if N == 0 {
+            return sample;
+        }
+        self.sum += sample as i64;
+        if let Some(replaced) = self.window.push(sample) {
+            self.sum -= replaced as i64;
+        }
+        (self.sum / self.window.len() as i64) as i32
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+        self.sum = 0;
+    }
+}
+
+/// Exponential smoothing `y += alpha * (x - y)`, which is also a first order
+/// IIR low-pass filter. The output starts at the first sample.
+#[derive(Copy, Clone, Debug)]
+pub struct ExponentialSmoothing {
+    alpha: i64,
+    /// Output with [FRACTIONAL_BITS] additional bits to avoid rounding bias
+    state: Option<i64>,
+}
+
+impl ExponentialSmoothing {
+    /// Creates the filter with the weight `alpha` of new samples, which has
+    /// to be in `1..=ONE`. Smaller values smooth more.
+    pub fn new(alpha: i32) -> Result<ExponentialSmoothing, OutOfRangeError> {
+        if alpha <= 0 || alpha > ONE {
+            return Err(OutOfRangeError);
+        }
+        Ok(ExponentialSmoothing {
+            alpha: alpha as i64,
+            state: None,
+        })
+    }
+
+    pub fn alpha(&self) -> i32 {
+        self.alpha as i32
+    }
+}
+
+impl Filter for ExponentialSmoothing {
+    fn process(&mut self, sample: i32) -> i32 {
+        let sample = (sample as i64) << FRACTIONAL_BITS;
+        let state = match self.state {
+            Some(state) => state + multiply(sample - state, self.alpha),
+            None => sample,
+        };
+        self.state = Some(state);
+        (state >> FRACTIONAL_BITS) as i32
+    }
+
+    fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+/// First order IIR low-pass filter
+#[derive(Copy, Clone, Debug)]
+pub struct LowPass {
+    smoothing: ExponentialSmoothing,
+}
+
+impl LowPass {
+    /// Creates a filter with the -3 dB point at `cutoff_hz` for samples taken
+    /// at `sample_rate_hz`
+    pub fn new(cutoff_hz: usize, sample_rate_hz: usize) -> Result<LowPass, OutOfRangeError> {
+        if cutoff_hz == 0 || sample_rate_hz == 0 {
+            return Err(OutOfRangeError);
+        }
+        // alpha = dt / (RC + dt) with RC = 1 / (2π fc)
+        let omega = TWO_PI_MILLI * cutoff_hz as u64;
+        let alpha = ((omega << FRACTIONAL_BITS) / (omega + 1000 * sample_rate_hz as u64)).max(1);
+        Ok(LowPass {
+            smoothing: ExponentialSmoothing::new(alpha as i32)?,
+        })
+    }
+
+    pub fn alpha(&self) -> i32 {
+        self.smoothing.alpha()
+    }
+}
+
+impl Filter for LowPass {
+    fn process(&mut self, sample: i32) -> i32 {
+        self.smoothing.process(sample)
+    }
+
+    fn reset(&mut self) {
+        self.smoothing.reset()
+    }
+}
+
+/// First order IIR high-pass filter `y = a * (y + x - x_prev)`, e.g. to
+/// remove a DC offset. The output starts at zero.
+#[derive(Copy, Clone, Debug)]
+pub struct HighPass {
+    alpha: i64,
+    previous_sample: Option<i32>,
+    /// Output with [FRACTIONAL_BITS] additional bits to avoid rounding bias
+    state: i64,
+}
+
+impl HighPass {
+    /// Creates a filter with the -3 dB point at `cutoff_hz` for samples taken
+    /// at `sample_rate_hz`
+    pub fn new(cutoff_hz: usize, sample_rate_hz: usize) -> Result<HighPass, OutOfRangeError> {
+        if cutoff_hz == 0 || sample_rate_hz == 0 {
+            return Err(OutOfRangeError);
+        }
+        // a = RC / (RC + dt) with RC = 1 / (2π fc)
+        let rate = 1000 * sample_rate_hz as u64;
+        let alpha = (rate << FRACTIONAL_BITS) / (TWO_PI_MILLI * cutoff_hz as u64 + rate);
+        Ok(HighPass {
+            alpha: alpha as i64,
+            previous_sample: None,
+            state: 0,
+        })
+    }
+
+    pub fn alpha(&self) -> i32 {
+        self.alpha as i32
+    }
+}
+
+impl Filter for HighPass {
+    fn process(&mut self, sample: i32) -> i32 {
+        let previous_sample = self.previous_sample.replace(sample).unwrap_or(sample);
+        let delta = (sample as i64 - previous_sample as i64) << FRACTIONAL_BITS;
+        self.state = multiply(self.state + delta, self.alpha);
+        // The output of a step across the full input range exceeds i32
+        (self.state >> FRACTIONAL_BITS)
+            .max(i32::min_value() as i64)
+            .min(i32::max_value() as i64) as i32
+    }
+
+    fn reset(&mut self) {
+        self.previous_sample = None;
+        self.state = 0;
+    }
+}
+
+/// A local maximum found by [PeakDetector]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Peak {
+    /// Value of the maximum
+    pub value: i32,
+    /// Number of samples processed before the maximum
+    pub index: usize,
+}
+
+/// Detects local maxima that stand out from the surrounding minima by at
+/// least `threshold`. Smaller wiggles, e.g. caused by noise, are ignored.
+///
+/// A peak is reported once the signal has fallen by `threshold` below it, so
+/// detection is delayed by the duration of that descent.
+#[derive(Copy, Clone, Debug)]
+pub struct PeakDetector {
+    threshold: i32,
+    rising: bool,
+    /// The maximum while rising or the minimum while falling
+    extreme: Option<Peak>,
+    index: usize,
+}
+
+impl PeakDetector {
+    pub fn new(threshold: i32) -> PeakDetector {
+        PeakDetector {
+            threshold,
+            rising: true,
+            extreme: None,
+            index: 0,
+        }
+    }
+
+    pub fn threshold(&self) -> i32 {
+        self.threshold
+    }
+
+    /// Feeds `sample` into the detector and returns a peak once it is
+    /// confirmed
+    pub fn process(&mut self, sample: i32) -> Option<Peak> {
+        let current = Peak {
+            value: sample,
+            index: self.index,
+        };
+        self.index += 1;
+        let extreme = match self.extreme {
+            Some(extreme) => extreme,
+            None => {
+                self.extreme = Some(current);
+                return None;
+            }
+        };
+        if self.rising {
+            if sample > extreme.value {
+                self.extreme = Some(current);
+            } else if sample <= extreme.value.saturating_sub(self.threshold) {
+                self.rising = false;
+                self.extreme = Some(current);
+                return Some(extreme);
+            }
+        } else if sample < extreme.value {
+            self.extreme = Some(current);
+        } else if sample >= extreme.value.saturating_add(self.threshold) {
+            self.rising = true;
+            self.extreme = Some(current);
+        }
+        None
+    }
+
+    pub fn reset(&mut self) {
+        *self = PeakDetector::new(self.threshold);
+    }
+}
+
+/// Multiplies `value` by the coefficient `alpha`. The product is computed in
+/// i128, as values with [FRACTIONAL_BITS] additional bits times `alpha`
+/// overflow i64 for full range i32 samples.
+fn multiply(value: i64, alpha: i64) -> i64 {
+    ((value as i128 * alpha as i128) >> FRACTIONAL_BITS) as i64
+}
+
+/// Integer square root, rounded down
+pub(crate) fn isqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+    let mut root = value;
+    let mut next = root / 2 + 1;
+    while next < root {
+        root = next;
+        next = (root + value / root) / 2;
+    }
+    root
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn window_statistics() {
+        let mut window = Window::<4>::default();
+        assert_eq!(window.rms(), None);
+        for &sample in &[7, -3, 4, 3, 4] {
+            window.push(sample);
+        }
+        assert!(window.is_full());
+        assert_eq!(window.min(), Some(-3));
+        assert_eq!(window.max(), Some(4));
+        assert_eq!(window.peak_to_peak(), Some(7));
+        assert_eq!(window.mean(), Some(2));
+        assert_eq!(window.rms(), Some(3));
+        assert_eq!(isqrt(u64::max_value()), u32::max_value() as u64);
+    }
+
+    #[test]
+    pub fn integer_square_root_rounds_down() {
+        for &(value, root) in &[(0, 0), (1, 1), (3, 1), (4, 2), (99, 9), (100, 10)] {
+            assert_eq!(isqrt(value), root);
+        }
+    }
+
+    #[test]
+    pub fn moving_average_and_smoothing_converge() {
+        let mut average = MovingAverage::<3>::default();
+        let outputs: Vec<_> = [3, 6, 9, 12, 12, 12]
+            .iter()
+            .map(|&sample| average.process(sample))
+            .collect();
+        assert_eq!(outputs, [3, 4, 6, 9, 11, 12]);
+
+        let mut smoothing = ExponentialSmoothing::new(ONE / 2).ok().unwrap();
+        let outputs: Vec<_> = [100, 0, 0, 0]
+            .iter()
+            .map(|&sample| smoothing.process(sample))
+            .collect();
+        assert_eq!(outputs, [100, 50, 25, 12]);
+        assert!(ExponentialSmoothing::new(0).is_err());
+        assert!(ExponentialSmoothing::new(ONE + 1).is_err());
+    }
+
+    #[test]
+    pub fn low_and_high_pass_split_dc_from_steps() {
+        let mut low_pass = LowPass::new(10, 1000).ok().unwrap();
+        let mut high_pass = HighPass::new(10, 1000).ok().unwrap();
+        let mut last = (0, 0);
+        for _ in 0..1000 {
+            last = (low_pass.process(1000), high_pass.process(1000));
+        }
+        assert_eq!(last, (1000, 0));
+
+        low_pass.process(2000);
+        assert_eq!(high_pass.process(2000), 1000 * high_pass.alpha() / ONE);
+        for _ in 0..1000 {
+            last = (low_pass.process(2000), high_pass.process(2000));
+        }
+        assert!(last.0 > 1990 && last.1 < 10);
+
+        let mut band_pass = (high_pass, low_pass);
+        band_pass.reset();
+        band_pass.process_slice(&[500u16; 4], &mut [0; 4]);
+        assert_eq!(band_pass.process(500), 0);
+    }
+
+    #[test]
+    pub fn full_range_samples_do_not_overflow() {
+        let mut window = Window::<8>::default();
+        for _ in 0..8 {
+            window.push(i32::min_value());
+        }
+        assert_eq!(window.rms(), Some(i32::max_value()));
+
+        let mut smoothing = ExponentialSmoothing::new(ONE).ok().unwrap();
+        smoothing.process(i32::min_value());
+        assert_eq!(smoothing.process(i32::max_value()), i32::max_value());
+
+        let mut high_pass = HighPass::new(1, 1000).ok().unwrap();
+        high_pass.process(i32::min_value());
+        assert_eq!(high_pass.process(i32::max_value()), i32::max_value());
+        assert_eq!(high_pass.process(i32::max_value()), i32::max_value());
+        high_pass.reset();
+        high_pass.process(i32::max_value());
+        assert_eq!(high_pass.process(i32::min_value()), i32::min_value());
+    }
+
+    #[test]
+    pub fn peaks_are_reported_after_descent() {
+        let mut detector = PeakDetector::new(5);
+        let signal = [0, 3, 10, 8, 12, 4, 2, 1, 5, 7, 3, 8, 0];
+        let peaks: Vec<_> = signal
+            .iter()
+            .filter_map(|&sample| detector.process(sample))
+            .collect();
+        assert_eq!(
+            peaks,
+            [
+                Peak {
+                    value: 12,
+                    index: 4
+                },
+                Peak {
+                    value: 8,
+                    index: 11
+                }
+            ]
+        );
+    }
+}
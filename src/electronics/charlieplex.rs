@@ -0,0 +1,321 @@
+use crate::gpio::GpioTriState;
+use crate::pwm;
+use crate::result::OutOfRangeError;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+use core::cell::Cell;
+
+/// Brightness of a LED that is on for its whole time slice
+pub const MAX_BRIGHTNESS: u8 = pwm::MAX_DUTY;
+
+/// Default time each pin spends as anode per frame. Long enough that most of
+/// each phase is slept rather than busy waited.
+pub const DEFAULT_PHASE_DURATION: Duration<usize> = Duration::from_ms(4);
+
+/// Charlieplexed LED array on `N` pins, addressing up to `N * (N - 1)` LEDs.
+///
+/// LED `index` has its anode at pin `index / (N - 1)` and its cathode at
+/// the `index % (N - 1)`-th of the remaining pins, see
+/// [led_pins()](Self::led_pins). Each frame consists of one phase per pin,
+/// in which that pin drives the anodes high while the cathodes of the lit
+/// LEDs are pulled low and all other pins float. Brightness is controlled by
+/// releasing cathodes before the end of the phase. The time up to each
+/// switching point is slept in whole milliseconds, only the sub-millisecond
+/// rest is busy waited. Add current limiting resistors to every pin, as all
+/// LEDs of an anode can be lit at once.
+///
+/// Example usage (3 pins, 6 LEDs):
+/// ```no_run
+/// # use libtock::electronics::Charlieplex;
+/// # use libtock::result::TockResult;
+/// # use libtock::timer::Duration;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut gpio0 = gpios.next().unwrap();
+/// let mut gpio1 = gpios.next().unwrap();
+/// let mut gpio2 = gpios.next().unwrap();
+/// let pins = [
+///     gpio0.enable_tri_state()?,
+///     gpio1.enable_tri_state()?,
+///     gpio2.enable_tri_state()?,
+/// ];
+/// let leds = Charlieplex::new(pins, &timer_driver)?;
+/// leds.set(0, 255).ok().unwrap();
+/// leds.set(5, 30).ok().unwrap();
+/// leds.run_for(Duration::from_ms(1000)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Charlieplex<'a, const N: usize> {
+    pins: [GpioTriState<'a>; N],
+    timer: &'a ParallelSleepDriver<'a>,
+    framebuffer: Cell<[[u8; N]; N]>,
+    phase_duration: Duration<usize>,
+    clock_frequency: usize,
+}
+
+impl<'a, const N: usize> Charlieplex<'a, N> {
+    /// Creates the array with all LEDs off and all pins floating
+    pub fn new(
+        pins: [GpioTriState<'a>; N],
+        timer: &'a ParallelSleepDriver<'a>,
+    ) -> TockResult<Charlieplex<'a, N>> {
+        for pin in pins.iter() {
+            pin.float()?;
+        }
+        let clock_frequency = timer.get_current_clock()?.clock_frequency().hz();
+        Ok(Charlieplex {
+            pins,
+            timer,
+            framebuffer: Cell::new([[0; N]; N]),
+            phase_duration: DEFAULT_PHASE_DURATION,
+            clock_frequency,
+        })
+    }
+
+    pub fn num_leds(&self) -> usize {
+        N * N.saturating_sub(1)
+    }
+
+    /// Returns the anode and cathode pin of LED `index`
+    pub fn led_pins(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.num_leds() {
+            return None;
+        }
+        let anode = index / (N - 1);
+        let cathode = index % (N - 1);
+        Some((
+            anode,
+            if cathode >= anode {
+                cathode + 1
+            } else {
+                cathode
+            },
+        ))
+    }
+
+    pub fn get(&self, index: usize) -> Option<u8> {
+        let (anode, cathode) = self.led_pins(index)?;
+        Some(self.framebuffer.get()[anode][cathode])
+    }
+
+    /// Sets the brightness of LED `index`. Takes effect with the next phase
+    /// of its anode.
+    pub fn set(&self, index: usize, brightness: u8) -> Result<(), OutOfRangeError> {
+        let (anode, cathode) = self.led_pins(index).ok_or(OutOfRangeError)?;
+        let mut framebuffer = self.framebuffer.get();
+        framebuffer[anode][cathode] = brightness;
+        self.framebuffer.set(framebuffer);
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        self.framebuffer.set([[0; N]; N]);
+    }
+
+    pub fn phase_duration(&self) -> Duration<usize> {
+        self.phase_duration
+    }
+
+    /// Sets the time each pin spends as anode per frame, which is
+    /// [DEFAULT_PHASE_DURATION] by default. Longer phases flicker, shorter
+    /// phases are mostly busy waited and leave less time for other work.
+    pub fn set_phase_duration(&mut self, phase_duration: Duration<usize>) {
+        self.phase_duration = Duration::from_ms(phase_duration.ms().max(1));
+    }
+
+    /// Shows a single frame
+    pub async fn refresh(&self) -> TockResult<()> {
+        let phase_ticks = timer::ms_to_ticks(self.phase_duration.ms(), self.clock_frequency);
+        for anode in 0..N {
+            let row = self.framebuffer.get()[anode];
+            let start = self.light_phase(anode, row, phase_ticks).await?;
+            self.timer.wait_until_ticks(start, phase_ticks).await?;
+            self.end_phase(anode, row)?;
+        }
+        Ok(())
+    }
+
+    /// Shows frames until `duration` has passed
+    pub async fn run_for(&self, duration: Duration<usize>) -> TockResult<()> {
+        let frame_ms = self.phase_duration.ms() * N;
+        for _ in 0..duration.ms() / frame_ms.max(1) {
+            self.refresh().await?;
+        }
+        Ok(())
+    }
+
+    /// Lights the LEDs of `anode` with the brightness levels in `row` and
+    /// waits until all dimmed LEDs have been switched off again. Returns the
+    /// clock value at the start of the phase.
+    async fn light_phase(
+        &self,
+        anode: usize,
+        row: [u8; N],
+        phase_ticks: usize,
+    ) -> TockResult<usize> {
+        for (cathode, pin) in self.pins.iter().enumerate() {
+            if cathode != anode && row[cathode] > 0 {
+                pin.drive_low()?;
+            }
+        }
+        let start = self.now()?;
+        self.pins[anode].drive_high()?;
+        let mut cathodes = row;
+        cathodes[anode] = 0;
+        pwm::switch_off_in_order(self.timer, start, phase_ticks, cathodes, |cathode| {
+            self.pins[cathode].float()
+        })
+        .await?;
+        Ok(start)
+    }
+
+    /// Floats the anode and the cathodes still lit
+    fn end_phase(&self, anode: usize, row: [u8; N]) -> TockResult<()> {
+        self.pins[anode].float()?;
+        for (cathode, pin) in self.pins.iter().enumerate() {
+            if cathode != anode && row[cathode] == MAX_BRIGHTNESS {
+                pin.float()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn now(&self) -> TockResult<usize> {
+        Ok(self.timer.get_current_clock()?.num_ticks() as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor;
+    use crate::gpio;
+    use crate::syscalls;
+    use crate::syscalls::raw::Event;
+    use core::task::Poll;
+
+    /// Runs `f` with a 3 pin array at the clock frequency `freq`. The clock
+    /// value equals `freq` while `f` runs.
+    fn with_charlieplex<F>(freq: isize, f: F) -> Vec<Event>
+    where
+        F: Fn(&Charlieplex<3>) -> TockResult<()>,
+    {
+        let checked = Cell::new(false);
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            next_return.set(3);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            next_return.set(0);
+            let mut gpios = gpio_driver.gpios();
+            let (mut gpio0, mut gpio1, mut gpio2) = (
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+            );
+            let pins = [
+                gpio0.enable_tri_state()?,
+                gpio1.enable_tri_state()?,
+                gpio2.enable_tri_state()?,
+            ];
+            next_return.set(freq);
+            let charlieplex = Charlieplex::new(pins, &timer_driver)?;
+            f(&charlieplex)?;
+            checked.set(true);
+            core::mem::forget(charlieplex);
+            Ok(())
+        });
+        assert!(checked.get());
+        events
+    }
+
+    #[test]
+    pub fn leds_are_mapped_to_pin_pairs() {
+        with_charlieplex(0, |charlieplex| {
+            assert_eq!(charlieplex.num_leds(), 6);
+            let pairs: Vec<_> = (0..7).map(|index| charlieplex.led_pins(index)).collect();
+            assert_eq!(
+                pairs,
+                [
+                    Some((0, 1)),
+                    Some((0, 2)),
+                    Some((1, 0)),
+                    Some((1, 2)),
+                    Some((2, 0)),
+                    Some((2, 1)),
+                    None,
+                ]
+            );
+            assert!(charlieplex.set(6, 1).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    pub fn phase_lights_cathodes_of_anode() {
+        let events = with_charlieplex(0, |charlieplex| {
+            charlieplex.set(2, MAX_BRIGHTNESS).ok().unwrap();
+            charlieplex.set(3, 100).ok().unwrap();
+            charlieplex.set(0, MAX_BRIGHTNESS).ok().unwrap();
+            let row = charlieplex.framebuffer.get()[1];
+            let light_phase = charlieplex.light_phase(1, row, 0);
+            ::futures::pin_mut!(light_phase);
+            match executor::poll(light_phase) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => panic!("phase did not complete"),
+            };
+            charlieplex.end_phase(1, row)
+        });
+        let commands: Vec<_> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Command(gpio::DRIVER_NUMBER, command, gpio_num, _) => {
+                    Some((command, gpio_num))
+                }
+                _ => None,
+            })
+            .skip(7)
+            .collect();
+        use gpio::command_nr::*;
+        assert_eq!(
+            commands,
+            [
+                (SET_LOW, 0),
+                (ENABLE_OUTPUT, 0),
+                (SET_LOW, 2),
+                (ENABLE_OUTPUT, 2),
+                (SET_HIGH, 1),
+                (ENABLE_OUTPUT, 1),
+                (ENABLE_INPUT, 2),
+                (ENABLE_INPUT, 1),
+                (ENABLE_INPUT, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn phases_are_slept() {
+        // At 1 kHz the default phase lasts 4 ticks, of which a LED at half
+        // brightness is lit for 2 ms
+        let events = with_charlieplex(1000, |charlieplex| {
+            charlieplex.set(0, 128).ok().unwrap();
+            let refresh = charlieplex.refresh();
+            ::futures::pin_mut!(refresh);
+            assert!(executor::poll(refresh).is_pending());
+            Ok(())
+        });
+        assert!(events.iter().any(|event| match event {
+            Event::Command(timer::DRIVER_NUMBER, timer::command_nr::SET_ALARM, _, _) => true,
+            _ => false,
+        }));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::electronics::one_wire;
+use crate::electronics::one_wire::OneWire;
+use crate::electronics::one_wire::Rom;
+use crate::result::OtherError;
+use crate::result::TockResult;
+use crate::temperature::Temperature;
+use crate::timer::Duration;
+
+/// Family code in the ROM codes of DS18B20 sensors
+pub const FAMILY_CODE: u8 = 0x28;
+
+mod function_command {
+    pub const CONVERT_T: u8 = 0x44;
+    pub const READ_SCRATCHPAD: u8 = 0xBE;
+}
+
+/// Interval between checks whether a conversion has finished
+const POLL_INTERVAL: Duration<usize> = Duration::from_ms(10);
+
+/// Maximum conversion time at 12 bit resolution plus a margin
+const CONVERSION_TIMEOUT: Duration<usize> = Duration::from_ms(800);
+
+/// DS18B20 digital thermometer on a [OneWire] bus.
+///
+/// The sensor needs to be powered through its VDD pin, parasite power is not
+/// supported.
+///
+/// Example usage (measure all sensors on the bus):
+/// ```no_run
+/// # use libtock::electronics::Ds18b20;
+/// # use libtock::electronics::OneWire;
+/// # use libtock::result::TockResult;
+/// # async fn doc() -> TockResult<()> {
+/// # let mut drivers = libtock::retrieve_drivers()?;
+/// # let mut timer_driver = drivers.timer.create_timer_driver();
+/// # let timer_driver = timer_driver.activate()?;
+/// # let mut gpio_driver = drivers.gpio.init_driver()?;
+/// # let mut pin = gpio_driver.gpios().next().unwrap();
+/// let bus = OneWire::new(pin.enable_open_drain()?, &timer_driver)?;
+/// for rom in bus.devices() {
+///     if let Some(sensor) = Ds18b20::new(rom?) {
+///         let _temperature = sensor.measure(&bus).await?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Ds18b20 {
+    rom: Option<Rom>,
+}
+
+impl Ds18b20 {
+    /// Returns `None` if the ROM code does not belong to a DS18B20
+    pub fn new(rom: Rom) -> Option<Ds18b20> {
+        if rom.family_code() == FAMILY_CODE {
+            Some(Ds18b20 { rom: Some(rom) })
+        } else {
+            None
+        }
+    }
+
+    /// Addresses the sensor without its ROM code. Only works if it is the
+    /// only device on the bus.
+    pub fn only_device() -> Ds18b20 {
+        Ds18b20 { rom: None }
+    }
+
+    pub fn rom(&self) -> Option<Rom> {
+        self.rom
+    }
+
+    /// Starts a temperature conversion, which takes up to 750 ms
+    pub fn start_conversion(&self, bus: &OneWire) -> TockResult<()> {
+        self.address(bus)?;
+        bus.write_byte(function_command::CONVERT_T)
+    }
+
+    /// Reads the result of the last conversion
+    pub fn read_temperature(&self, bus: &OneWire) -> TockResult<Temperature> {
+        self.address(bus)?;
+        bus.write_byte(function_command::READ_SCRATCHPAD)?;
+        let mut scratchpad = [0; 9];
+        bus.read_bytes(&mut scratchpad)?;
+        temperature_from_scratchpad(scratchpad)
+    }
+
+    /// Starts a conversion and waits for its result. The sensor signals the
+    /// end of the conversion by answering read slots with 1. Fails with
+    /// [OtherError::Ds18b20ConversionTimeout] if it does not within 800 ms.
+    pub async fn measure(&self, bus: &OneWire<'_>) -> TockResult<Temperature> {
+        self.start_conversion(bus)?;
+        let max_polls = CONVERSION_TIMEOUT.ms() / POLL_INTERVAL.ms();
+        let mut polls = 0;
+        while !bus.read_bit()? {
+            if polls == max_polls {
+                return Err(OtherError::Ds18b20ConversionTimeout.into());
+            }
+            polls += 1;
+            bus.timer().sleep(POLL_INTERVAL).await?;
+        }
+        self.read_temperature(bus)
+    }
+
+    fn address(&self, bus: &OneWire) -> TockResult<()> {
+        match self.rom {
+            Some(rom) => bus.select(rom),
+            None => bus.skip_rom(),
+        }
+    }
+}
+
+/// Checks the CRC of the scratchpad and extracts the temperature. A bus held
+/// low reads as all zeros, which has a valid CRC, so it is rejected as well.
+fn temperature_from_scratchpad(scratchpad: [u8; 9]) -> TockResult<Temperature> {
+    if scratchpad.iter().all(|&byte| byte == 0) || one_wire::crc8(&scratchpad[..8]) != scratchpad[8]
+    {
+        return Err(OtherError::OneWireCrcMismatch.into());
+    }
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    Ok(temperature_from_raw(raw))
+}
+
+/// Converts the raw reading in 1/16 °C, truncating towards zero
+fn temperature_from_raw(raw: i16) -> Temperature {
+    Temperature::from_centi_celsius(raw as isize * 100 / 16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn raw_readings_are_converted() {
+        let cases = [
+            (0x07D0, 12500),
+            (0x0191, 2506),
+            (0x0008, 50),
+            (0x0000, 0),
+            (0xFFF8u16 as i16, -50),
+            (0xFF5Eu16 as i16, -1012),
+            (0xFC90u16 as i16, -5500),
+        ];
+        for &(raw, centi_celsius) in &cases {
+            assert_eq!(temperature_from_raw(raw).in_centi_celsius(), centi_celsius);
+        }
+    }
+
+    #[test]
+    pub fn scratchpads_are_validated() {
+        let mut scratchpad = [0x91, 0x01, 0x4B, 0x46, 0x7F, 0xFF, 0x0F, 0x10, 0];
+        scratchpad[8] = one_wire::crc8(&scratchpad[..8]);
+        assert_eq!(
+            temperature_from_scratchpad(scratchpad)
+                .ok()
+                .unwrap()
+                .in_centi_celsius(),
+            2506
+        );
+        scratchpad[0] ^= 1;
+        assert!(temperature_from_scratchpad(scratchpad).is_err());
+        assert!(temperature_from_scratchpad([0; 9]).is_err());
+    }
+
+    #[test]
+    pub fn only_ds18b20_roms_are_accepted() {
+        let mut bytes = [FAMILY_CODE, 1, 2, 3, 4, 5, 6, 0];
+        bytes[7] = one_wire::crc8(&bytes[..7]);
+        assert!(Ds18b20::new(Rom::from_bytes(bytes).unwrap()).is_some());
+        bytes[0] = 0x10;
+        bytes[7] = one_wire::crc8(&bytes[..7]);
+        assert!(Ds18b20::new(Rom::from_bytes(bytes).unwrap()).is_none());
+    }
+}
@@ -0,0 +1,502 @@
+use crate::result::OutOfRangeError;
+use crate::result::TockError;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+use core::fmt;
+use embedded_hal::digital::v2::OutputPin;
+
+mod instruction {
+    pub const CLEAR_DISPLAY: u8 = 0x01;
+    pub const RETURN_HOME: u8 = 0x02;
+    pub const ENTRY_MODE_SET: u8 = 0x04;
+    pub const DISPLAY_CONTROL: u8 = 0x08;
+    pub const CURSOR_OR_DISPLAY_SHIFT: u8 = 0x10;
+    pub const FUNCTION_SET: u8 = 0x20;
+    pub const SET_CGRAM_ADDRESS: u8 = 0x40;
+    pub const SET_DDRAM_ADDRESS: u8 = 0x80;
+}
+
+mod flag {
+    pub const ENTRY_INCREMENT: u8 = 0x02;
+    pub const DISPLAY_ON: u8 = 0x04;
+    pub const CURSOR_ON: u8 = 0x02;
+    pub const BLINK_ON: u8 = 0x01;
+    pub const SHIFT_DISPLAY: u8 = 0x08;
+    pub const SHIFT_RIGHT: u8 = 0x04;
+    pub const EIGHT_BIT_MODE: u8 = 0x10;
+    pub const TWO_LINES: u8 = 0x08;
+}
+
+/// Execution time of all instructions except clear and return home
+const INSTRUCTION_TIME_US: usize = 40;
+/// Execution time of clear and return home
+const LONG_INSTRUCTION_TIME: Duration<usize> = Duration::from_ms(2);
+
+/// Character LCD with an HD44780 compatible controller.
+///
+/// `N` is the number of data pins: 4 (connected to D4 to D7) or 8
+/// (connected to D0 to D7). The R/W pin has to be tied to ground. Use
+/// [init()](Self::init) before writing to the display.
+///
+/// Example usage (16x2 display in 4-bit mode):
+/// ```no_run
+/// # use core::fmt::Write;
+/// # use libtock::electronics::Hd44780;
+/// # use libtock::result::TockResult;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut rs = gpios.next().unwrap();
+/// let mut enable = gpios.next().unwrap();
+/// let mut d4 = gpios.next().unwrap();
+/// let mut d5 = gpios.next().unwrap();
+/// let mut d6 = gpios.next().unwrap();
+/// let mut d7 = gpios.next().unwrap();
+/// let data_pins = [
+///     d4.enable_output()?,
+///     d5.enable_output()?,
+///     d6.enable_output()?,
+///     d7.enable_output()?,
+/// ];
+/// let mut lcd = Hd44780::new(
+///     rs.enable_output()?,
+///     enable.enable_output()?,
+///     data_pins,
+///     &timer_driver,
+///     16,
+///     2,
+/// )?;
+/// lcd.init().await?;
+/// writeln!(lcd, "Hello")?;
+/// write!(lcd, "World")?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Hd44780<'a, P, const N: usize> {
+    rs_pin: P,
+    enable_pin: P,
+    data_pins: [P; N],
+    timer: &'a ParallelSleepDriver<'a>,
+    instruction_ticks: usize,
+    columns: u8,
+    rows: u8,
+    row: u8,
+    display_control: u8,
+}
+
+impl<'a, P: OutputPin, const N: usize> Hd44780<'a, P, N>
+where
+    P::Error: Into<TockError>,
+{
+    /// Fails if `N` is neither 4 nor 8 or if the display has more than four
+    /// rows or more than 40 columns.
+    pub fn new(
+        rs_pin: P,
+        enable_pin: P,
+        data_pins: [P; N],
+        timer: &'a ParallelSleepDriver<'a>,
+        columns: u8,
+        rows: u8,
+    ) -> TockResult<Hd44780<'a, P, N>> {
+        if (N != 4 && N != 8) || rows == 0 || rows > 4 || columns == 0 || columns > 40 {
+            return Err(OutOfRangeError.into());
+        }
+        let clock_frequency = timer.get_current_clock()?.clock_frequency().hz();
+        Ok(Hd44780 {
+            rs_pin,
+            enable_pin,
+            data_pins,
+            timer,
+            instruction_ticks: timer::us_to_ticks(INSTRUCTION_TIME_US, clock_frequency),
+            columns,
+            rows,
+            row: 0,
+            display_control: flag::DISPLAY_ON,
+        })
+    }
+
+    pub fn columns(&self) -> u8 {
+        self.columns
+    }
+
+    pub fn rows(&self) -> u8 {
+        self.rows
+    }
+
+    /// Runs the initialization sequence, which works regardless of the
+    /// state the controller is in. Clears the display and switches it on
+    /// with the cursor hidden.
+    pub async fn init(&mut self) -> TockResult<()> {
+        set_pin(&mut self.enable_pin, false)?;
+        set_pin(&mut self.rs_pin, false)?;
+        // Wait for the supply voltage to settle after power on
+        self.timer.sleep(Duration::from_ms(50)).await?;
+        for &wait_ms in &[5, 1, 1] {
+            self.write_interface(0x30)?;
+            self.timer.sleep(Duration::from_ms(wait_ms)).await?;
+        }
+        let mut function_set = instruction::FUNCTION_SET;
+        if N == 8 {
+            function_set |= flag::EIGHT_BIT_MODE;
+        } else {
+            self.write_interface(0x20)?;
+            self.wait_for_instruction()?;
+        }
+        if self.rows > 1 {
+            function_set |= flag::TWO_LINES;
+        }
+        self.command(function_set)?;
+        self.command(instruction::DISPLAY_CONTROL)?;
+        self.clear().await?;
+        self.command(instruction::ENTRY_MODE_SET | flag::ENTRY_INCREMENT)?;
+        self.display_control = flag::DISPLAY_ON;
+        self.update_display_control()
+    }
+
+    /// Clears the display and moves the cursor to the top left
+    pub async fn clear(&mut self) -> TockResult<()> {
+        self.command(instruction::CLEAR_DISPLAY)?;
+        self.row = 0;
+        self.timer.sleep(LONG_INSTRUCTION_TIME).await
+    }
+
+    /// Moves the cursor to the top left and undoes display shifts
+    pub async fn home(&mut self) -> TockResult<()> {
+        self.command(instruction::RETURN_HOME)?;
+        self.row = 0;
+        self.timer.sleep(LONG_INSTRUCTION_TIME).await
+    }
+
+    /// Moves the cursor to the 0-based `column` and `row`
+    pub fn set_cursor(&mut self, column: u8, row: u8) -> TockResult<()> {
+        if column >= self.columns || row >= self.rows {
+            return Err(OutOfRangeError.into());
+        }
+        // Rows 2 and 3 of four line displays continue rows 0 and 1
+        let row_start = match row {
+            0 => 0x00,
+            1 => 0x40,
+            2 => self.columns,
+            _ => 0x40 + self.columns,
+        };
+        self.row = row;
+        self.command(instruction::SET_DDRAM_ADDRESS | (row_start + column))
+    }
+
+    pub fn set_display_on(&mut self, on: bool) -> TockResult<()> {
+        self.set_display_control_flag(flag::DISPLAY_ON, on)
+    }
+
+    /// Shows an underline at the cursor position
+    pub fn set_cursor_visible(&mut self, visible: bool) -> TockResult<()> {
+        self.set_display_control_flag(flag::CURSOR_ON, visible)
+    }
+
+    /// Blinks the character at the cursor position
+    pub fn set_cursor_blinking(&mut self, blinking: bool) -> TockResult<()> {
+        self.set_display_control_flag(flag::BLINK_ON, blinking)
+    }
+
+    /// Moves the cursor by one position without changing the content
+    pub fn move_cursor(&mut self, right: bool) -> TockResult<()> {
+        self.shift(0, right)
+    }
+
+    /// Shifts the content of all rows by one position
+    pub fn scroll_display(&mut self, right: bool) -> TockResult<()> {
+        self.shift(flag::SHIFT_DISPLAY, right)
+    }
+
+    /// Uploads a custom 5x8 glyph, which is then shown for the character
+    /// code `location` (0 to 7). The lower five bits of each row are used,
+    /// the first row is at the top. Moves the cursor to the top left.
+    pub fn create_char(&mut self, location: u8, glyph: [u8; 8]) -> TockResult<()> {
+        if location > 7 {
+            return Err(OutOfRangeError.into());
+        }
+        self.command(instruction::SET_CGRAM_ADDRESS | (location << 3))?;
+        for &row in &glyph {
+            self.write_data(row & 0x1F)?;
+        }
+        self.set_cursor(0, 0)
+    }
+
+    /// Writes a character code at the cursor position and advances the
+    /// cursor
+    pub fn write_data(&mut self, data: u8) -> TockResult<()> {
+        set_pin(&mut self.rs_pin, true)?;
+        self.write_byte(data)
+    }
+
+    fn command(&mut self, command: u8) -> TockResult<()> {
+        set_pin(&mut self.rs_pin, false)?;
+        self.write_byte(command)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> TockResult<()> {
+        if N == 8 {
+            self.write_interface(byte)?;
+        } else {
+            self.write_interface(byte & 0xF0)?;
+            self.write_interface(byte << 4)?;
+        }
+        self.wait_for_instruction()
+    }
+
+    /// Puts the upper `N` bits of `value` on the data pins and pulses the
+    /// enable pin
+    fn write_interface(&mut self, value: u8) -> TockResult<()> {
+        for (index, pin) in self.data_pins.iter_mut().enumerate() {
+            set_pin(pin, (value >> (8 - N + index)) & 1 == 1)?;
+        }
+        set_pin(&mut self.enable_pin, true)?;
+        set_pin(&mut self.enable_pin, false)
+    }
+
+    fn wait_for_instruction(&self) -> TockResult<()> {
+        self.timer.busy_wait_ticks(self.instruction_ticks)
+    }
+
+    fn set_display_control_flag(&mut self, flag: u8, set: bool) -> TockResult<()> {
+        if set {
+            self.display_control |= flag;
+        } else {
+            self.display_control &= !flag;
+        }
+        self.update_display_control()
+    }
+
+    fn update_display_control(&mut self) -> TockResult<()> {
+        self.command(instruction::DISPLAY_CONTROL | self.display_control)
+    }
+
+    fn shift(&mut self, display: u8, right: bool) -> TockResult<()> {
+        let direction = if right { flag::SHIFT_RIGHT } else { 0 };
+        self.command(instruction::CURSOR_OR_DISPLAY_SHIFT | display | direction)
+    }
+}
+
+/// Writes text at the cursor position. A newline moves the cursor to the
+/// start of the next row, wrapping around to the first row. Characters
+/// `'\u{0}'` to `'\u{7}'` show the custom glyphs, other characters outside
+/// of ASCII are shown as `?`.
+impl<'a, P: OutputPin, const N: usize> fmt::Write for Hd44780<'a, P, N>
+where
+    P::Error: Into<TockError>,
+{
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for character in string.chars() {
+            let result = match character {
+                '\n' => self.set_cursor(0, (self.row + 1) % self.rows),
+                '\r' => self.set_cursor(0, self.row),
+                character if character.is_ascii() => self.write_data(character as u8),
+                _ => self.write_data(b'?'),
+            };
+            result.map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_pin<P: OutputPin>(pin: &mut P, high: bool) -> TockResult<()>
+where
+    P::Error: Into<TockError>,
+{
+    if high { pin.set_high() } else { pin.set_low() }.map_err(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::electronics::test_pin::pin;
+    use crate::electronics::test_pin::TestPin;
+    use crate::executor;
+    use crate::syscalls;
+    use crate::syscalls::raw::Event;
+    use core::cell::RefCell;
+    use core::fmt::Write;
+    use core::task::Poll;
+
+    const RS: usize = 0;
+    const ENABLE: usize = 1;
+    const DATA: usize = 2;
+
+    /// Returns the levels of RS and the data lines at each falling edge of
+    /// the enable pin
+    fn transfers(log: &[(usize, bool)], data_pins: usize) -> Vec<(bool, u8)> {
+        let mut levels = [false; 10];
+        let mut transfers = Vec::new();
+        for &(id, high) in log {
+            if id == ENABLE && levels[ENABLE] && !high {
+                let value = (0..data_pins)
+                    .filter(|&index| levels[DATA + index])
+                    .fold(0, |value, index| value | 1 << (8 - data_pins + index));
+                transfers.push((levels[RS], value));
+            }
+            levels[id] = high;
+        }
+        transfers
+    }
+
+    /// Combines pairs of nibbles transferred in 4-bit mode into bytes
+    fn bytes_of_nibbles(nibbles: &[(bool, u8)]) -> Vec<(bool, u8)> {
+        nibbles
+            .chunks(2)
+            .map(|pair| {
+                assert_eq!(pair[0].0, pair[1].0, "RS changed within a byte");
+                (pair[0].0, pair[0].1 | pair[1].1 >> 4)
+            })
+            .collect()
+    }
+
+    fn with_lcd<'a, F, const N: usize>(
+        log: &'a RefCell<Vec<(usize, bool)>>,
+        data_pins: [TestPin<'a>; N],
+        f: F,
+    ) where
+        F: Fn(&mut Hd44780<TestPin<'a>, N>) -> TockResult<()>,
+    {
+        let mut data_pins = Some(data_pins);
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|_| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            let mut lcd = Hd44780::new(
+                pin(RS, log),
+                pin(ENABLE, log),
+                data_pins.take().unwrap(),
+                &timer_driver,
+                16,
+                2,
+            )?;
+            f(&mut lcd)
+        });
+    }
+
+    fn data_pins_8_bit(log: &RefCell<Vec<(usize, bool)>>) -> [TestPin; 8] {
+        [
+            pin(DATA, log),
+            pin(DATA + 1, log),
+            pin(DATA + 2, log),
+            pin(DATA + 3, log),
+            pin(DATA + 4, log),
+            pin(DATA + 5, log),
+            pin(DATA + 6, log),
+            pin(DATA + 7, log),
+        ]
+    }
+
+    #[test]
+    pub fn init_runs_power_on_sequence_in_4_bit_mode() {
+        let log = RefCell::new(Vec::new());
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|_| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            let data_pins = [
+                pin(DATA, &log),
+                pin(DATA + 1, &log),
+                pin(DATA + 2, &log),
+                pin(DATA + 3, &log),
+            ];
+            let mut lcd = Hd44780::new(
+                pin(RS, &log),
+                pin(ENABLE, &log),
+                data_pins,
+                &timer_driver,
+                16,
+                2,
+            )?;
+            let init = lcd.init();
+            ::futures::pin_mut!(init);
+            loop {
+                if let Poll::Ready(result) = executor::poll(init.as_mut()) {
+                    return result;
+                }
+            }
+        });
+        let nibbles = transfers(&log.borrow(), 4);
+        assert_eq!(
+            nibbles[..4],
+            [(false, 0x30), (false, 0x30), (false, 0x30), (false, 0x20)]
+        );
+        assert_eq!(
+            bytes_of_nibbles(&nibbles[4..]),
+            [
+                (false, 0x28),
+                (false, 0x08),
+                (false, 0x01),
+                (false, 0x06),
+                (false, 0x0C)
+            ]
+        );
+        // Power on delay, three waits after the 8-bit function sets and
+        // clear
+        let alarms = events
+            .iter()
+            .filter(|event| match event {
+                Event::Command(timer::DRIVER_NUMBER, timer::command_nr::SET_ALARM, _, _) => true,
+                _ => false,
+            })
+            .count();
+        assert!(alarms >= 5);
+    }
+
+    #[test]
+    pub fn text_is_sent_as_data_in_4_bit_mode() {
+        let log = RefCell::new(Vec::new());
+        let data_pins = [
+            pin(DATA, &log),
+            pin(DATA + 1, &log),
+            pin(DATA + 2, &log),
+            pin(DATA + 3, &log),
+        ];
+        with_lcd(&log, data_pins, |lcd| {
+            write!(lcd, "Hi\nä")?;
+            Ok(())
+        });
+        assert_eq!(
+            bytes_of_nibbles(&transfers(&log.borrow(), 4)),
+            [(true, b'H'), (true, b'i'), (false, 0xC0), (true, b'?')]
+        );
+    }
+
+    #[test]
+    pub fn cursor_positions_in_8_bit_mode() {
+        let log = RefCell::new(Vec::new());
+        with_lcd(&log, data_pins_8_bit(&log), |lcd| {
+            lcd.set_cursor(3, 1)?;
+            lcd.set_cursor_visible(true)?;
+            lcd.set_cursor_blinking(true)?;
+            lcd.scroll_display(false)?;
+            assert!(lcd.set_cursor(16, 0).is_err());
+            Ok(())
+        });
+        assert_eq!(
+            transfers(&log.borrow(), 8),
+            [(false, 0xC3), (false, 0x0E), (false, 0x0F), (false, 0x18)]
+        );
+    }
+
+    #[test]
+    pub fn glyphs_are_written_to_cgram() {
+        let log = RefCell::new(Vec::new());
+        with_lcd(&log, data_pins_8_bit(&log), |lcd| {
+            lcd.create_char(1, [0xFF, 0x11, 0, 0, 0, 0, 0, 0x0A])?;
+            assert!(lcd.create_char(8, [0; 8]).is_err());
+            Ok(())
+        });
+        let transfers = transfers(&log.borrow(), 8);
+        assert_eq!(transfers[0], (false, 0x48));
+        assert_eq!(
+            transfers[1..9].iter().map(|t| t.1).collect::<Vec<_>>(),
+            [0x1F, 0x11, 0, 0, 0, 0, 0, 0x0A]
+        );
+        assert_eq!(transfers[9..], [(false, 0x80)]);
+    }
+}
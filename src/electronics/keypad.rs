@@ -0,0 +1,314 @@
+use crate::debounce::Debouncer;
+use crate::gpio::GpioOpenDrain;
+use crate::gpio::GpioRead;
+use crate::gpio::GpioState;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KeyEvent<K> {
+    Down(K),
+    Up(K),
+}
+
+/// Matrix keypad with `ROWS` row lines and `COLS` column lines.
+///
+/// The rows are driven low one at a time while the columns are read. Only
+/// the scanned row is driven, the other rows are released, so keys held in
+/// the same column never short two outputs. The row pins need to be enabled
+/// with [enable_open_drain()](crate::gpio::Gpio::enable_open_drain), the
+/// column pins with
+/// [ResistorMode::PullUp](crate::gpio::ResistorMode::PullUp). Each key is
+/// debounced separately, so any number of keys can be held at the same time.
+/// Keypads without diodes may report ghost keys if three keys forming the
+/// corners of a rectangle are held.
+///
+/// Example usage (4x3 phone keypad):
+/// ```no_run
+/// # use libtock::electronics::keypad::KeyEvent;
+/// # use libtock::electronics::Keypad;
+/// # use libtock::gpio::ResistorMode;
+/// # use libtock::result::TockResult;
+/// # use libtock::timer::Duration;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// # let mut g = || gpios.next().unwrap();
+/// # let (mut r0, mut r1, mut r2, mut r3) = (g(), g(), g(), g());
+/// # let (mut c0, mut c1, mut c2) = (g(), g(), g());
+/// let rows = [
+///     r0.enable_open_drain()?,
+///     r1.enable_open_drain()?,
+///     r2.enable_open_drain()?,
+///     r3.enable_open_drain()?,
+/// ];
+/// let columns = [
+///     c0.enable_input(ResistorMode::PullUp)?,
+///     c1.enable_input(ResistorMode::PullUp)?,
+///     c2.enable_input(ResistorMode::PullUp)?,
+/// ];
+/// let keymap = [
+///     ['1', '2', '3'],
+///     ['4', '5', '6'],
+///     ['7', '8', '9'],
+///     ['*', '0', '#'],
+/// ];
+/// let mut keypad = Keypad::new(rows, columns, keymap, &timer_driver, Duration::from_ms(20))?;
+///
+/// loop {
+///     if let KeyEvent::Down(key) = keypad.next_event().await? {
+///         // Handle key
+///     }
+/// }
+/// # }
+/// ```
+pub struct Keypad<'a, K, const ROWS: usize, const COLS: usize> {
+    rows: [GpioOpenDrain<'a>; ROWS],
+    columns: [GpioRead<'a>; COLS],
+    keymap: [[K; COLS]; ROWS],
+    timer: &'a ParallelSleepDriver<'a>,
+    debouncers: [[Debouncer; COLS]; ROWS],
+    reported: [[bool; COLS]; ROWS],
+    scan_interval: Duration<usize>,
+}
+
+impl<'a, K: Copy, const ROWS: usize, const COLS: usize> Keypad<'a, K, ROWS, COLS> {
+    /// Creates the keypad and releases all rows. The matrix is scanned four
+    /// times during `settle_time`, but at most once per millisecond.
+    pub fn new(
+        rows: [GpioOpenDrain<'a>; ROWS],
+        columns: [GpioRead<'a>; COLS],
+        keymap: [[K; COLS]; ROWS],
+        timer: &'a ParallelSleepDriver<'a>,
+        settle_time: Duration<usize>,
+    ) -> TockResult<Keypad<'a, K, ROWS, COLS>> {
+        for row in rows.iter() {
+            row.release()?;
+        }
+        let freq = timer.get_current_clock()?.clock_frequency().hz();
+        let debouncer = Debouncer::new(false, timer::ms_to_ticks(settle_time.ms(), freq));
+        Ok(Keypad {
+            rows,
+            columns,
+            keymap,
+            timer,
+            debouncers: [[debouncer; COLS]; ROWS],
+            reported: [[false; COLS]; ROWS],
+            scan_interval: Duration::from_ms((settle_time.ms() / 4).max(1)),
+        })
+    }
+
+    pub fn keymap(&self) -> &[[K; COLS]; ROWS] {
+        &self.keymap
+    }
+
+    /// The debounced state of the key at `row` and `column`
+    pub fn is_pressed(&self, row: usize, column: usize) -> bool {
+        self.debouncers[row][column].state()
+    }
+
+    /// All keys currently held, in row-major order
+    pub fn pressed_keys<'b>(&'b self) -> impl Iterator<Item = K> + 'b {
+        self.debouncers
+            .iter()
+            .zip(self.keymap.iter())
+            .flat_map(|(debouncers, keys)| debouncers.iter().zip(keys.iter()))
+            .filter(|(debouncer, _)| debouncer.state())
+            .map(|(_, &key)| key)
+    }
+
+    /// Samples every key once. A row is released again before the next one
+    /// is driven low.
+    pub fn scan(&mut self) -> TockResult<()> {
+        let now = self.timer.get_current_clock()?.num_ticks() as usize;
+        self.scan_at(now)
+    }
+
+    fn scan_at(&mut self, now: usize) -> TockResult<()> {
+        for (row, debouncers) in self.rows.iter().zip(self.debouncers.iter_mut()) {
+            row.set_low()?;
+            for (column, debouncer) in self.columns.iter().zip(debouncers.iter_mut()) {
+                let sample = match column.read() {
+                    Ok(state) => state == GpioState::Low,
+                    Err(error) => {
+                        let _ = row.release();
+                        return Err(error);
+                    }
+                };
+                debouncer.update(sample, now);
+            }
+            row.release()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next key whose debounced state has not been reported yet,
+    /// without scanning the matrix
+    pub fn poll_event(&mut self) -> Option<KeyEvent<K>> {
+        for row in 0..ROWS {
+            for column in 0..COLS {
+                let pressed = self.debouncers[row][column].state();
+                if pressed != self.reported[row][column] {
+                    self.reported[row][column] = pressed;
+                    let key = self.keymap[row][column];
+                    return Some(if pressed {
+                        KeyEvent::Down(key)
+                    } else {
+                        KeyEvent::Up(key)
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Scans the matrix periodically until a key is pressed or released.
+    /// Changes of several keys in the same scan are returned one after
+    /// another.
+    pub async fn next_event(&mut self) -> TockResult<KeyEvent<K>> {
+        loop {
+            if let Some(event) = self.poll_event() {
+                return Ok(event);
+            }
+            self.scan()?;
+            if let Some(event) = self.poll_event() {
+                return Ok(event);
+            }
+            self.timer.sleep(self.scan_interval).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpio;
+    use crate::gpio::ResistorMode;
+    use crate::syscalls;
+    use crate::syscalls::raw::Event;
+    use core::cell::Cell;
+
+    /// Runs `f` with a 2x2 keypad at a clock frequency of 1 kHz, so the
+    /// settle time of 20 ms equals 20 ticks
+    fn with_keypad<F>(f: F) -> Vec<Event>
+    where
+        F: Fn(&mut Keypad<char, 2, 2>, &syscalls::raw::NextReturn) -> TockResult<()>,
+    {
+        let checked = Cell::new(false);
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            next_return.set(4);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            next_return.set(0);
+            let mut gpios = gpio_driver.gpios();
+            let (mut r0, mut r1, mut c0, mut c1) = (
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+            );
+            let rows = [r0.enable_open_drain()?, r1.enable_open_drain()?];
+            let columns = [
+                c0.enable_input(ResistorMode::PullUp)?,
+                c1.enable_input(ResistorMode::PullUp)?,
+            ];
+            next_return.set(1000);
+            let mut keypad = Keypad::new(
+                rows,
+                columns,
+                [['a', 'b'], ['c', 'd']],
+                &timer_driver,
+                Duration::from_ms(20),
+            )?;
+            f(&mut keypad, next_return)?;
+            checked.set(true);
+            core::mem::forget(keypad);
+            Ok(())
+        });
+        assert!(checked.get());
+        events
+    }
+
+    #[test]
+    pub fn scan_reports_all_changed_keys() {
+        let events = with_keypad(|keypad, next_return| {
+            // All columns read low, i.e. all keys are held
+            next_return.set(0);
+            keypad.scan_at(0)?;
+            assert_eq!(keypad.poll_event(), None);
+            keypad.scan_at(20)?;
+            assert!(keypad.is_pressed(1, 0));
+            assert_eq!(
+                keypad.pressed_keys().collect::<Vec<_>>(),
+                ['a', 'b', 'c', 'd']
+            );
+            for &key in &['a', 'b', 'c', 'd'] {
+                assert_eq!(keypad.poll_event(), Some(KeyEvent::Down(key)));
+            }
+            assert_eq!(keypad.poll_event(), None);
+
+            next_return.set(1);
+            keypad.scan_at(30)?;
+            keypad.scan_at(50)?;
+            assert_eq!(keypad.pressed_keys().count(), 0);
+            assert_eq!(keypad.poll_event(), Some(KeyEvent::Up('a')));
+            Ok(())
+        });
+        let scan: Vec<_> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Command(gpio::DRIVER_NUMBER, command, gpio_num, _) => {
+                    Some((command, gpio_num))
+                }
+                _ => None,
+            })
+            .skip_while(|&(command, _)| command != gpio::command_nr::SET_LOW)
+            .take(8)
+            .collect();
+        assert_eq!(
+            scan,
+            [
+                (gpio::command_nr::SET_LOW, 0),
+                (gpio::command_nr::ENABLE_OUTPUT, 0),
+                (gpio::command_nr::READ, 2),
+                (gpio::command_nr::READ, 3),
+                (gpio::command_nr::ENABLE_INPUT, 0),
+                (gpio::command_nr::SET_LOW, 1),
+                (gpio::command_nr::ENABLE_OUTPUT, 1),
+                (gpio::command_nr::READ, 2),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn bouncing_contacts_are_ignored() {
+        with_keypad(|keypad, next_return| {
+            next_return.set(0);
+            keypad.scan_at(0)?;
+            next_return.set(1);
+            keypad.scan_at(5)?;
+            next_return.set(0);
+            keypad.scan_at(10)?;
+            keypad.scan_at(29)?;
+            assert_eq!(keypad.poll_event(), None);
+            keypad.scan_at(30)?;
+            assert_eq!(keypad.poll_event(), Some(KeyEvent::Down('a')));
+
+            next_return.set(1);
+            keypad.scan_at(31)?;
+            next_return.set(0);
+            keypad.scan_at(32)?;
+            keypad.scan_at(100)?;
+            assert_eq!(keypad.pressed_keys().count(), 4);
+            Ok(())
+        });
+    }
+}
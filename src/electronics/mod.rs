@@ -1,3 +1,59 @@
+pub mod charlieplex;
+pub mod ds18b20;
+pub mod hd44780;
+pub mod keypad;
+pub mod one_wire;
+pub mod rotary_encoder;
+pub mod seven_segment;
 pub mod shift_register;
+pub mod soft_i2c;
+pub mod soft_spi;
+pub mod stepper;
+pub mod ultrasonic;
 
+pub use self::charlieplex::Charlieplex;
+pub use self::ds18b20::Ds18b20;
+pub use self::hd44780::Hd44780;
+pub use self::keypad::Keypad;
+pub use self::one_wire::OneWire;
+pub use self::rotary_encoder::RotaryEncoder;
+pub use self::seven_segment::SevenSegment;
+pub use self::shift_register::InputShiftRegister;
 pub use self::shift_register::ShiftRegister;
+pub use self::soft_i2c::SoftI2c;
+pub use self::soft_spi::SoftSpi;
+pub use self::stepper::Stepper;
+pub use self::ultrasonic::Ultrasonic;
+
+#[cfg(test)]
+pub(crate) mod test_pin {
+    use crate::result::TockError;
+    use crate::result::TockResult;
+    use core::cell::RefCell;
+    use embedded_hal::digital::v2::OutputPin;
+
+    /// Output pin recording each write as `(id, high)` in a log shared by
+    /// all pins of a test
+    pub struct TestPin<'a> {
+        id: usize,
+        pub log: &'a RefCell<Vec<(usize, bool)>>,
+    }
+
+    impl<'a> OutputPin for TestPin<'a> {
+        type Error = TockError;
+
+        fn set_low(&mut self) -> TockResult<()> {
+            self.log.borrow_mut().push((self.id, false));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> TockResult<()> {
+            self.log.borrow_mut().push((self.id, true));
+            Ok(())
+        }
+    }
+
+    pub fn pin(id: usize, log: &RefCell<Vec<(usize, bool)>>) -> TestPin {
+        TestPin { id, log }
+    }
+}
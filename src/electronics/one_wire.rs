@@ -0,0 +1,510 @@
+use crate::gpio::GpioOpenDrain;
+use crate::gpio::GpioState;
+use crate::result::OtherError;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::ParallelSleepDriver;
+
+mod rom_command {
+    pub const SEARCH_ROM: u8 = 0xF0;
+    pub const READ_ROM: u8 = 0x33;
+    pub const MATCH_ROM: u8 = 0x55;
+    pub const SKIP_ROM: u8 = 0xCC;
+}
+
+mod timing_us {
+    pub const RESET_LOW: usize = 480;
+    pub const PRESENCE_WAIT: usize = 70;
+    pub const RESET_RECOVERY: usize = 410;
+    pub const SLOT: usize = 60;
+}
+
+/// Dallas/Maxim CRC8 (polynomial x^8 + x^5 + x^4 + 1) as used in ROM codes
+/// and scratchpads of 1-Wire devices
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix == 1 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+/// 64 bit ROM code identifying a device on a 1-Wire bus
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Rom {
+    bytes: [u8; 8],
+}
+
+impl Rom {
+    /// Creates a ROM code from its bytes in bus order (family code first,
+    /// CRC last). Returns `None` if the CRC does not match.
+    pub fn from_bytes(bytes: [u8; 8]) -> Option<Rom> {
+        if crc8(&bytes[..7]) == bytes[7] {
+            Some(Rom { bytes })
+        } else {
+            None
+        }
+    }
+
+    pub fn bytes(self) -> [u8; 8] {
+        self.bytes
+    }
+
+    pub fn family_code(self) -> u8 {
+        self.bytes[0]
+    }
+}
+
+/// Bit level access to the bus. Separated from [OneWire] so the ROM search
+/// can be tested against simulated devices.
+trait Transport {
+    fn reset(&self) -> TockResult<bool>;
+
+    fn write_bit(&self, bit: bool) -> TockResult<()>;
+
+    fn read_bit(&self) -> TockResult<bool>;
+
+    fn write_byte(&self, byte: u8) -> TockResult<()> {
+        for shift in 0..8 {
+            self.write_bit((byte >> shift) & 1 == 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// 1-Wire bus master using a single GPIO pin.
+///
+/// The pin is emulated as an open-drain output, see
+/// [Gpio::enable_open_drain()](crate::gpio::Gpio::enable_open_drain), and
+/// needs an external pull-up resistor (typically 4.7 kΩ). Time slots are
+/// timed by busy waiting on the kernel's clock, which needs to run at 32 kHz
+/// or faster.
+///
+/// Example usage:
+/// ```no_run
+/// # use libtock::electronics::OneWire;
+/// # use libtock::result::TockResult;
+/// # fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut pin = gpio_driver.gpios().next().unwrap();
+/// let bus = OneWire::new(pin.enable_open_drain()?, &timer_driver)?;
+///
+/// for rom in bus.devices() {
+///     let _family_code = rom?.family_code();
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct OneWire<'a> {
+    pin: GpioOpenDrain<'a>,
+    timer: &'a ParallelSleepDriver<'a>,
+    clock_frequency: usize,
+}
+
+impl<'a> OneWire<'a> {
+    /// Creates the bus master and releases the line
+    pub fn new(
+        pin: GpioOpenDrain<'a>,
+        timer: &'a ParallelSleepDriver<'a>,
+    ) -> TockResult<OneWire<'a>> {
+        let clock_frequency = timer.get_current_clock()?.clock_frequency().hz();
+        pin.release()?;
+        Ok(OneWire {
+            pin,
+            timer,
+            clock_frequency,
+        })
+    }
+
+    pub(crate) fn timer(&self) -> &'a ParallelSleepDriver<'a> {
+        self.timer
+    }
+
+    /// Sends a reset pulse. Returns `true` if at least one device answered
+    /// with a presence pulse.
+    pub fn reset(&self) -> TockResult<bool> {
+        Transport::reset(self)
+    }
+
+    pub fn write_bit(&self, bit: bool) -> TockResult<()> {
+        Transport::write_bit(self, bit)
+    }
+
+    pub fn read_bit(&self) -> TockResult<bool> {
+        Transport::read_bit(self)
+    }
+
+    /// Sends `byte`, least significant bit first
+    pub fn write_byte(&self, byte: u8) -> TockResult<()> {
+        Transport::write_byte(self, byte)
+    }
+
+    /// Receives a byte, least significant bit first
+    pub fn read_byte(&self) -> TockResult<u8> {
+        let mut byte = 0;
+        for shift in 0..8 {
+            if self.read_bit()? {
+                byte |= 1 << shift;
+            }
+        }
+        Ok(byte)
+    }
+
+    pub fn write_bytes(&self, bytes: &[u8]) -> TockResult<()> {
+        for &byte in bytes {
+            self.write_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_bytes(&self, buffer: &mut [u8]) -> TockResult<()> {
+        for byte in buffer.iter_mut() {
+            *byte = self.read_byte()?;
+        }
+        Ok(())
+    }
+
+    /// Resets the bus and addresses the device with the given ROM code.
+    /// Function commands sent afterwards only reach this device.
+    pub fn select(&self, rom: Rom) -> TockResult<()> {
+        self.reset_and_command(rom_command::MATCH_ROM)?;
+        self.write_bytes(&rom.bytes)
+    }
+
+    /// Resets the bus and addresses all devices at once
+    pub fn skip_rom(&self) -> TockResult<()> {
+        self.reset_and_command(rom_command::SKIP_ROM)
+    }
+
+    /// Reads the ROM code of the only device on the bus
+    pub fn read_rom(&self) -> TockResult<Rom> {
+        self.reset_and_command(rom_command::READ_ROM)?;
+        let mut bytes = [0; 8];
+        self.read_bytes(&mut bytes)?;
+        Rom::from_bytes(bytes).ok_or_else(|| OtherError::OneWireCrcMismatch.into())
+    }
+
+    /// Returns an iterator over the ROM codes of all devices on the bus
+    pub fn devices(&self) -> Devices<'_, 'a> {
+        Devices {
+            bus: self,
+            search: RomSearch::default(),
+        }
+    }
+
+    fn reset_and_command(&self, command: u8) -> TockResult<()> {
+        if !self.reset()? {
+            return Err(OtherError::OneWireNoDevicePresent.into());
+        }
+        self.write_byte(command)
+    }
+
+    fn delay_us(&self, duration_us: usize) -> TockResult<()> {
+        let ticks = timer::us_to_ticks(duration_us, self.clock_frequency);
+        self.timer.busy_wait_ticks(ticks)
+    }
+}
+
+impl<'a> Transport for OneWire<'a> {
+    fn reset(&self) -> TockResult<bool> {
+        self.pin.set_low()?;
+        self.delay_us(timing_us::RESET_LOW)?;
+        self.pin.release()?;
+        self.delay_us(timing_us::PRESENCE_WAIT)?;
+        let presence = self.pin.read()? == GpioState::Low;
+        self.delay_us(timing_us::RESET_RECOVERY)?;
+        Ok(presence)
+    }
+
+    fn write_bit(&self, bit: bool) -> TockResult<()> {
+        self.pin.set_low()?;
+        if bit {
+            self.pin.release()?;
+            self.delay_us(timing_us::SLOT)
+        } else {
+            self.delay_us(timing_us::SLOT)?;
+            self.pin.release()
+        }
+    }
+
+    fn read_bit(&self) -> TockResult<bool> {
+        self.pin.set_low()?;
+        self.pin.release()?;
+        let bit = self.pin.read()? == GpioState::High;
+        self.delay_us(timing_us::SLOT)?;
+        Ok(bit)
+    }
+}
+
+/// State of the ROM search algorithm described in Maxim application note
+/// 187
+#[derive(Copy, Clone, Debug, Default)]
+struct RomSearch {
+    rom: [u8; 8],
+    /// 1-based bit position of the last branch where the 0 path was taken
+    last_discrepancy: usize,
+    finished: bool,
+}
+
+impl RomSearch {
+    fn next_device<T: Transport>(&mut self, bus: &T) -> TockResult<Option<Rom>> {
+        if self.finished || !bus.reset()? {
+            self.finished = true;
+            return Ok(None);
+        }
+        bus.write_byte(rom_command::SEARCH_ROM)?;
+        let mut last_zero = 0;
+        for bit_number in 1..=64 {
+            let byte = (bit_number - 1) / 8;
+            let mask = 1 << ((bit_number - 1) % 8);
+            let id_bit = bus.read_bit()?;
+            let complement_bit = bus.read_bit()?;
+            let direction = match (id_bit, complement_bit) {
+                (true, true) => {
+                    self.finished = true;
+                    return Ok(None);
+                }
+                (false, false) => {
+                    let direction = if bit_number < self.last_discrepancy {
+                        self.rom[byte] & mask != 0
+                    } else {
+                        bit_number == self.last_discrepancy
+                    };
+                    if !direction {
+                        last_zero = bit_number;
+                    }
+                    direction
+                }
+                (id_bit, _) => id_bit,
+            };
+            if direction {
+                self.rom[byte] |= mask;
+            } else {
+                self.rom[byte] &= !mask;
+            }
+            bus.write_bit(direction)?;
+        }
+        self.last_discrepancy = last_zero;
+        self.finished = last_zero == 0;
+        match Rom::from_bytes(self.rom) {
+            Some(rom) => Ok(Some(rom)),
+            None => {
+                self.finished = true;
+                Err(OtherError::OneWireCrcMismatch.into())
+            }
+        }
+    }
+}
+
+/// Iterator over the ROM codes of the devices on a bus. Ends after the
+/// first error.
+pub struct Devices<'b, 'a> {
+    bus: &'b OneWire<'a>,
+    search: RomSearch,
+}
+
+impl<'b, 'a> Iterator for Devices<'b, 'a> {
+    type Item = TockResult<Rom>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.search.next_device(self.bus).transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpio::command_nr;
+    use crate::gpio::DRIVER_NUMBER;
+    use crate::syscalls;
+    use crate::syscalls::raw::Event;
+    use core::cell::Cell;
+    use core::cell::RefCell;
+
+    const ROM_BYTES: [u8; 8] = [0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xA2];
+
+    fn record_one_wire<F>(line_level: isize, f: F) -> Vec<Event>
+    where
+        F: Fn(&OneWire) -> TockResult<()>,
+    {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            next_return.set(1);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            let mut pin = gpio_driver.gpios().next().unwrap();
+            next_return.set(0);
+            let bus = OneWire::new(pin.enable_open_drain()?, &timer_driver)?;
+            next_return.set(line_level);
+            f(&bus)
+        })
+    }
+
+    /// Replays the recorded commands and decodes written bits. The clock is
+    /// not advancing, but each delay still reads it once, so a bit is 0 if
+    /// the line was released only after a delay.
+    fn written_bits(events: &[Event]) -> Vec<bool> {
+        let mut low_since_delay = None;
+        let mut bits = Vec::new();
+        for event in events {
+            match *event {
+                Event::Command(DRIVER_NUMBER, command_nr::ENABLE_OUTPUT, _, _) => {
+                    low_since_delay = Some(false)
+                }
+                Event::Command(DRIVER_NUMBER, command_nr::ENABLE_INPUT, _, _) => {
+                    bits.extend(low_since_delay.take().map(|delayed| !delayed))
+                }
+                Event::Command(DRIVER_NUMBER, _, _, _) => {}
+                Event::Command(..) => {
+                    low_since_delay = low_since_delay.map(|_| true);
+                }
+                _ => {}
+            }
+        }
+        bits
+    }
+
+    #[test]
+    pub fn crc8_of_rom_code() {
+        assert_eq!(crc8(&ROM_BYTES[..7]), 0xA2);
+        assert!(Rom::from_bytes(ROM_BYTES).is_some());
+        let mut corrupted = ROM_BYTES;
+        corrupted[3] ^= 0x10;
+        assert!(Rom::from_bytes(corrupted).is_none());
+    }
+
+    #[test]
+    pub fn bytes_are_sent_lsb_first() {
+        let events = record_one_wire(0, |bus| bus.write_byte(0x35));
+        let expected = [true, false, true, false, true, true, false, false];
+        assert_eq!(written_bits(&events), expected);
+    }
+
+    #[test]
+    pub fn presence_pulse_is_detected() {
+        let present = Cell::new(None);
+        record_one_wire(0, |bus| {
+            present.set(Some(bus.reset()?));
+            Ok(())
+        });
+        assert_eq!(present.get(), Some(true));
+
+        let selected = Cell::new(None);
+        record_one_wire(1, |bus| {
+            selected.set(Some(matches!(
+                bus.select(Rom::from_bytes(ROM_BYTES).unwrap()),
+                Err(crate::result::TockError::Other(
+                    OtherError::OneWireNoDevicePresent
+                ))
+            )));
+            Ok(())
+        });
+        assert_eq!(selected.get(), Some(true));
+    }
+
+    /// Devices answering a ROM search. A line is low if any active device
+    /// pulls it low.
+    struct SimulatedBus {
+        roms: Vec<[u8; 8]>,
+        active: RefCell<Vec<bool>>,
+        commands_bits: Cell<usize>,
+        position: Cell<usize>,
+        complement: Cell<bool>,
+    }
+
+    impl SimulatedBus {
+        fn new(roms: Vec<[u8; 8]>) -> SimulatedBus {
+            let active = RefCell::new(vec![true; roms.len()]);
+            SimulatedBus {
+                roms,
+                active,
+                commands_bits: Cell::new(0),
+                position: Cell::new(0),
+                complement: Cell::new(false),
+            }
+        }
+
+        fn bit(rom: [u8; 8], position: usize) -> bool {
+            rom[position / 8] & (1 << (position % 8)) != 0
+        }
+    }
+
+    impl Transport for SimulatedBus {
+        fn reset(&self) -> TockResult<bool> {
+            self.active.replace(vec![true; self.roms.len()]);
+            self.commands_bits.set(0);
+            self.position.set(0);
+            self.complement.set(false);
+            Ok(!self.roms.is_empty())
+        }
+
+        fn write_bit(&self, bit: bool) -> TockResult<()> {
+            if self.commands_bits.get() < 8 {
+                self.commands_bits.set(self.commands_bits.get() + 1);
+                return Ok(());
+            }
+            let position = self.position.get();
+            for (active, rom) in self.active.borrow_mut().iter_mut().zip(&self.roms) {
+                *active &= Self::bit(*rom, position) == bit;
+            }
+            self.position.set(position + 1);
+            Ok(())
+        }
+
+        fn read_bit(&self) -> TockResult<bool> {
+            let complement = self.complement.get();
+            self.complement.set(!complement);
+            let position = self.position.get();
+            Ok(self
+                .roms
+                .iter()
+                .zip(self.active.borrow().iter())
+                .filter(|&(_, &active)| active)
+                .all(|(&rom, _)| Self::bit(rom, position) != complement))
+        }
+    }
+
+    fn rom_with_serial(serial: u8) -> [u8; 8] {
+        let mut bytes = [0x28, serial, 0x5A, 0, 0, 0, 0, 0];
+        bytes[7] = crc8(&bytes[..7]);
+        bytes
+    }
+
+    #[test]
+    pub fn search_finds_all_devices() {
+        let roms = vec![
+            rom_with_serial(0x13),
+            rom_with_serial(0x12),
+            rom_with_serial(0x93),
+            ROM_BYTES,
+        ];
+        let bus = SimulatedBus::new(roms.clone());
+        let mut search = RomSearch::default();
+        let mut found = Vec::new();
+        while let Some(rom) = search.next_device(&bus).unwrap() {
+            found.push(rom.bytes());
+        }
+        found.sort();
+        let mut expected = roms;
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    pub fn search_on_empty_bus_finds_nothing() {
+        let bus = SimulatedBus::new(Vec::new());
+        assert_eq!(RomSearch::default().next_device(&bus).unwrap(), None);
+    }
+}
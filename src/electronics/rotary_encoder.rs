@@ -0,0 +1,414 @@
+use crate::event_queue::EventQueue;
+use crate::gpio::GpioRead;
+use crate::gpio::GpioState;
+use crate::gpio::TriggerType;
+use crate::result::OutOfRangeError;
+use crate::result::TockResult;
+use core::cell::Cell;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+use futures::stream::Stream;
+
+/// Position change per transition, indexed by `old_state * 4 + new_state`
+/// where a state is `(a << 1) | b`. Transitions where both channels change
+/// at once cannot be decoded and are marked with `None`.
+const TRANSITIONS: [Option<i8>; 16] = [
+    Some(0),
+    Some(-1),
+    Some(1),
+    None,
+    Some(1),
+    Some(0),
+    None,
+    Some(-1),
+    Some(-1),
+    None,
+    Some(0),
+    Some(1),
+    None,
+    Some(1),
+    Some(-1),
+    Some(0),
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Channel A leads channel B
+    Clockwise,
+    /// Channel B leads channel A
+    CounterClockwise,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EncoderEvent {
+    Step(Direction),
+    ButtonPressed,
+    ButtonReleased,
+}
+
+/// Quadrature decoding state machine operating on the levels of the two
+/// channels.
+///
+/// Contact bounce moves the state back and forth and cancels out. A step is
+/// only reported once `transitions_per_step` transitions in the same
+/// direction have accumulated.
+#[derive(Copy, Clone, Debug)]
+pub struct QuadratureDecoder {
+    state: u8,
+    transitions_per_step: i8,
+    accumulated: i8,
+    invalid_transitions: usize,
+}
+
+impl QuadratureDecoder {
+    /// `transitions_per_step` is 4 for most encoders with detents, 1 reports
+    /// every edge. Valid values are 1, 2 and 4.
+    pub fn new(
+        a: bool,
+        b: bool,
+        transitions_per_step: usize,
+    ) -> Result<QuadratureDecoder, OutOfRangeError> {
+        match transitions_per_step {
+            1 | 2 | 4 => Ok(QuadratureDecoder {
+                state: encode_state(a, b),
+                transitions_per_step: transitions_per_step as i8,
+                accumulated: 0,
+                invalid_transitions: 0,
+            }),
+            _ => Err(OutOfRangeError),
+        }
+    }
+
+    /// Feeds the current levels of both channels. Returns the direction if a
+    /// full step was completed.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<Direction> {
+        let new_state = encode_state(a, b);
+        let transition = TRANSITIONS[usize::from(self.state * 4 + new_state)];
+        self.state = new_state;
+        match transition {
+            Some(delta) => self.accumulated += delta,
+            None => {
+                self.invalid_transitions += 1;
+                return None;
+            }
+        }
+        if self.accumulated >= self.transitions_per_step {
+            self.accumulated = 0;
+            Some(Direction::Clockwise)
+        } else if self.accumulated <= -self.transitions_per_step {
+            self.accumulated = 0;
+            Some(Direction::CounterClockwise)
+        } else {
+            None
+        }
+    }
+
+    /// Number of transitions where both channels changed at once, which
+    /// hints at missed edges
+    pub fn invalid_transitions(&self) -> usize {
+        self.invalid_transitions
+    }
+}
+
+fn encode_state(a: bool, b: bool) -> u8 {
+    ((a as u8) << 1) | b as u8
+}
+
+/// Incremental rotary encoder with optional push button, decoded from GPIO
+/// edge interrupts.
+///
+/// The encoder does not subscribe by itself. Instead,
+/// [handle_event()](Self::handle_event) has to be called from the callback
+/// passed to [Gpios::subscribe()](crate::gpio::Gpios::subscribe) of the
+/// iterator that handed out the pins.
+///
+/// Example usage:
+/// ```no_run
+/// # use futures::stream::StreamExt;
+/// # use libtock::electronics::rotary_encoder::EncoderEvent;
+/// # use libtock::electronics::RotaryEncoder;
+/// # use libtock::gpio::GpioState;
+/// # use libtock::gpio::ResistorMode;
+/// # use libtock::result::TockResult;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut a = gpios.next().unwrap();
+/// let mut b = gpios.next().unwrap();
+/// let mut button = gpios.next().unwrap();
+/// let encoder = RotaryEncoder::new(
+///     a.enable_input(ResistorMode::PullUp)?,
+///     b.enable_input(ResistorMode::PullUp)?,
+/// )?
+/// .with_button(button.enable_input(ResistorMode::PullUp)?, GpioState::Low)?;
+///
+/// let mut callback = |gpio_num, state| encoder.handle_event(gpio_num, state);
+/// let _subscription = gpios.subscribe(&mut callback)?;
+///
+/// let mut events = encoder.events();
+/// while let Some(event) = events.next().await {
+///     if let EncoderEvent::ButtonPressed = event {
+///         encoder.set_position(0);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct RotaryEncoder<'a> {
+    a: GpioRead<'a>,
+    b: GpioRead<'a>,
+    button: Option<(GpioRead<'a>, GpioState)>,
+    levels: Cell<(bool, bool)>,
+    decoder: Cell<QuadratureDecoder>,
+    position: Cell<isize>,
+    button_pressed: Cell<bool>,
+    events: EventQueue<EncoderEvent>,
+}
+
+impl<'a> RotaryEncoder<'a> {
+    /// Reads the initial levels of both channels and enables interrupts on
+    /// both edges. Reports a step every 4 transitions.
+    pub fn new(a: GpioRead<'a>, b: GpioRead<'a>) -> TockResult<RotaryEncoder<'a>> {
+        let levels = (bool::from(a.read()?), bool::from(b.read()?));
+        a.enable_interrupt(TriggerType::EitherEdge)?;
+        b.enable_interrupt(TriggerType::EitherEdge)?;
+        let decoder = QuadratureDecoder::new(levels.0, levels.1, 4).ok().unwrap();
+        Ok(RotaryEncoder {
+            a,
+            b,
+            button: None,
+            levels: Cell::new(levels),
+            decoder: Cell::new(decoder),
+            position: Cell::new(0),
+            button_pressed: Cell::new(false),
+            events: EventQueue::default(),
+        })
+    }
+
+    /// Changes the number of transitions per reported step, see
+    /// [QuadratureDecoder::new()]
+    pub fn with_transitions_per_step(
+        self,
+        transitions_per_step: usize,
+    ) -> Result<RotaryEncoder<'a>, OutOfRangeError> {
+        let (a, b) = self.levels.get();
+        self.decoder
+            .set(QuadratureDecoder::new(a, b, transitions_per_step)?);
+        Ok(self)
+    }
+
+    /// Adds the push button of the encoder, which reads `pressed_state`
+    /// while pressed.
+    pub fn with_button(
+        mut self,
+        button: GpioRead<'a>,
+        pressed_state: GpioState,
+    ) -> TockResult<RotaryEncoder<'a>> {
+        self.button_pressed.set(button.read()? == pressed_state);
+        button.enable_interrupt(TriggerType::EitherEdge)?;
+        self.button = Some((button, pressed_state));
+        Ok(self)
+    }
+
+    /// Processes a GPIO interrupt. Events of other pins are ignored.
+    pub fn handle_event(&self, gpio_num: usize, state: GpioState) {
+        let (mut a, mut b) = self.levels.get();
+        if gpio_num == self.a.gpio_num() {
+            a = state.into();
+        } else if gpio_num == self.b.gpio_num() {
+            b = state.into();
+        } else {
+            match self.button {
+                Some((ref button, pressed_state)) if button.gpio_num() == gpio_num => {
+                    self.update_button(state == pressed_state)
+                }
+                _ => {}
+            }
+            return;
+        }
+        self.levels.set((a, b));
+        let mut decoder = self.decoder.get();
+        let step = decoder.update(a, b);
+        self.decoder.set(decoder);
+        if let Some(direction) = step {
+            let position = self.position.get();
+            self.position.set(match direction {
+                Direction::Clockwise => position.wrapping_add(1),
+                Direction::CounterClockwise => position.wrapping_sub(1),
+            });
+            self.events.push(EncoderEvent::Step(direction));
+        }
+    }
+
+    /// Number of steps clockwise minus the number of steps counter-clockwise
+    pub fn position(&self) -> isize {
+        self.position.get()
+    }
+
+    pub fn set_position(&self, position: isize) {
+        self.position.set(position);
+    }
+
+    pub fn is_button_pressed(&self) -> bool {
+        self.button_pressed.get()
+    }
+
+    /// See [QuadratureDecoder::invalid_transitions()]
+    pub fn invalid_transitions(&self) -> usize {
+        self.decoder.get().invalid_transitions()
+    }
+
+    /// Number of events that were dropped because they were not consumed in
+    /// time
+    pub fn overflow_count(&self) -> usize {
+        self.events.overflow_count()
+    }
+
+    /// Removes the oldest buffered event
+    pub fn next_event(&self) -> Option<EncoderEvent> {
+        self.events.pop()
+    }
+
+    /// Returns a stream of the buffered events
+    pub fn events(&self) -> EncoderEvents {
+        EncoderEvents { encoder: self }
+    }
+
+    fn update_button(&self, pressed: bool) {
+        if pressed == self.button_pressed.get() {
+            return;
+        }
+        self.button_pressed.set(pressed);
+        self.events.push(if pressed {
+            EncoderEvent::ButtonPressed
+        } else {
+            EncoderEvent::ButtonReleased
+        });
+    }
+}
+
+/// Stream of events created by [RotaryEncoder::events()]
+pub struct EncoderEvents<'a> {
+    encoder: &'a RotaryEncoder<'a>,
+}
+
+impl<'a> Stream for EncoderEvents<'a> {
+    type Item = EncoderEvent;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.encoder.next_event() {
+            Some(event) => Poll::Ready(Some(event)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpio::ResistorMode;
+    use crate::syscalls;
+
+    const CLOCKWISE: [(bool, bool); 4] =
+        [(true, false), (true, true), (false, true), (false, false)];
+
+    fn level(high: bool) -> GpioState {
+        if high {
+            GpioState::High
+        } else {
+            GpioState::Low
+        }
+    }
+
+    fn feed(decoder: &mut QuadratureDecoder, levels: &[(bool, bool)]) -> Vec<Direction> {
+        levels
+            .iter()
+            .filter_map(|&(a, b)| decoder.update(a, b))
+            .collect()
+    }
+
+    #[test]
+    pub fn full_cycles_are_decoded_as_steps() {
+        let mut decoder = QuadratureDecoder::new(false, false, 4).ok().unwrap();
+        assert_eq!(feed(&mut decoder, &CLOCKWISE), [Direction::Clockwise]);
+        let mut counter_clockwise = CLOCKWISE;
+        counter_clockwise.reverse();
+        counter_clockwise.rotate_left(1);
+        assert_eq!(
+            feed(&mut decoder, &counter_clockwise),
+            [Direction::CounterClockwise]
+        );
+        assert_eq!(decoder.invalid_transitions(), 0);
+    }
+
+    #[test]
+    pub fn bouncing_channel_cancels_out() {
+        let mut decoder = QuadratureDecoder::new(false, false, 4).ok().unwrap();
+        let bouncing = [
+            (true, false),
+            (false, false),
+            (true, false),
+            (true, true),
+            (true, false),
+            (true, true),
+            (false, true),
+            (false, false),
+        ];
+        assert_eq!(feed(&mut decoder, &bouncing), [Direction::Clockwise]);
+    }
+
+    #[test]
+    pub fn invalid_transitions_are_counted_and_ignored() {
+        let mut decoder = QuadratureDecoder::new(false, false, 1).ok().unwrap();
+        assert!(feed(&mut decoder, &[(true, true), (false, false)]).is_empty());
+        assert_eq!(decoder.invalid_transitions(), 2);
+        assert_eq!(feed(&mut decoder, &[(true, false)]), [Direction::Clockwise]);
+        assert!(QuadratureDecoder::new(false, false, 3).is_err());
+    }
+
+    #[test]
+    pub fn interrupts_update_position_and_queue_events() {
+        let checked = Cell::new(false);
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(3);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            next_return.set(0);
+            let mut gpios = gpio_driver.gpios();
+            let (mut a, mut b, mut button) = (
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+            );
+            let encoder = RotaryEncoder::new(
+                a.enable_input(ResistorMode::PullUp)?,
+                b.enable_input(ResistorMode::PullUp)?,
+            )?
+            .with_button(button.enable_input(ResistorMode::PullUp)?, GpioState::High)?;
+            for &(a, b) in CLOCKWISE.iter().chain(CLOCKWISE.iter()) {
+                encoder.handle_event(0, level(a));
+                encoder.handle_event(1, level(b));
+            }
+            encoder.handle_event(2, GpioState::High);
+            encoder.handle_event(2, GpioState::High);
+            encoder.handle_event(5, GpioState::High);
+            assert_eq!(encoder.position(), 2);
+            assert!(encoder.is_button_pressed());
+            assert_eq!(
+                encoder.next_event(),
+                Some(EncoderEvent::Step(Direction::Clockwise))
+            );
+            assert_eq!(
+                encoder.next_event(),
+                Some(EncoderEvent::Step(Direction::Clockwise))
+            );
+            assert_eq!(encoder.next_event(), Some(EncoderEvent::ButtonPressed));
+            assert_eq!(encoder.next_event(), None);
+            checked.set(true);
+            Ok(())
+        });
+        assert!(checked.get());
+    }
+}
@@ -0,0 +1,430 @@
+use crate::electronics::ShiftRegister;
+use crate::result::OutOfRangeError;
+use crate::result::TockError;
+use crate::result::TockResult;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+use core::cell::Cell;
+use core::cell::RefCell;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Segment bits of the encoded characters: bit 0 is segment a, bit 6 is
+/// segment g and bit 7 is the decimal point.
+pub mod segment {
+    pub const A: u8 = 0x01;
+    pub const B: u8 = 0x02;
+    pub const C: u8 = 0x04;
+    pub const D: u8 = 0x08;
+    pub const E: u8 = 0x10;
+    pub const F: u8 = 0x20;
+    pub const G: u8 = 0x40;
+    pub const DP: u8 = 0x80;
+}
+
+const HEX_DIGITS: [u8; 16] = [
+    0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+];
+
+/// Returns the segments of a hexadecimal digit
+pub fn encode_digit(digit: u8) -> Option<u8> {
+    HEX_DIGITS.get(digit as usize).cloned()
+}
+
+/// Returns the segments of a character. Letters without a recognizable
+/// representation (like K, M, W or X) are not supported.
+pub fn encode(character: char) -> Option<u8> {
+    if let Some(digit) = character.to_digit(16) {
+        return encode_digit(digit as u8);
+    }
+    let segments = match character {
+        ' ' => 0x00,
+        '-' => 0x40,
+        '_' => 0x08,
+        '=' => 0x48,
+        '\'' => 0x20,
+        'G' | 'g' => 0x3D,
+        'H' => 0x76,
+        'h' => 0x74,
+        'I' | 'i' => 0x30,
+        'J' | 'j' => 0x1E,
+        'L' | 'l' => 0x38,
+        'N' | 'n' => 0x54,
+        'O' => 0x3F,
+        'o' => 0x5C,
+        'P' | 'p' => 0x73,
+        'Q' | 'q' => 0x67,
+        'R' | 'r' => 0x50,
+        'S' | 's' => 0x6D,
+        'T' | 't' => 0x78,
+        'U' => 0x3E,
+        'u' => 0x1C,
+        'Y' | 'y' => 0x6E,
+        _ => return None,
+    };
+    Some(segments)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Polarity {
+    /// Segments are lit by driving their pins high and the common pin of the
+    /// active digit low
+    CommonCathode,
+    /// Segments are lit by driving their pins low and the common pin of the
+    /// active digit high
+    CommonAnode,
+}
+
+/// Pins driving the segments and digit select lines of a display.
+pub trait SegmentOutput {
+    type Error;
+
+    /// Sets the segment lines to `segments` and the digit select lines to
+    /// `digits`, a set bit meaning a high level. `digits` is `None` for
+    /// displays with a single, permanently enabled digit.
+    fn write(&mut self, segments: u8, digits: Option<u8>) -> Result<(), Self::Error>;
+}
+
+/// Shifts out the digit select lines first, so they end up in the second
+/// register of the chain, and the segment lines second.
+impl<P: OutputPin> SegmentOutput for ShiftRegister<P> {
+    type Error = P::Error;
+
+    fn write(&mut self, segments: u8, digits: Option<u8>) -> Result<(), P::Error> {
+        match digits {
+            Some(digits) => self.write_bytes(&[digits, segments]),
+            None => self.write_bytes(&[segments]),
+        }
+    }
+}
+
+/// Display connected directly to GPIO pins. The segment pins are ordered
+/// a to g followed by the decimal point.
+pub struct GpioSegments<P, const DIGITS: usize> {
+    segment_pins: [P; 8],
+    digit_pins: [P; DIGITS],
+}
+
+impl<P: OutputPin, const DIGITS: usize> GpioSegments<P, DIGITS> {
+    pub fn new(segment_pins: [P; 8], digit_pins: [P; DIGITS]) -> GpioSegments<P, DIGITS> {
+        GpioSegments {
+            segment_pins,
+            digit_pins,
+        }
+    }
+}
+
+impl<P: OutputPin, const DIGITS: usize> SegmentOutput for GpioSegments<P, DIGITS> {
+    type Error = P::Error;
+
+    fn write(&mut self, segments: u8, digits: Option<u8>) -> Result<(), P::Error> {
+        let digits = digits.unwrap_or(0);
+        for (index, pin) in self.digit_pins.iter_mut().enumerate() {
+            set_pin(pin, (digits >> index) & 1 == 1)?;
+        }
+        for (index, pin) in self.segment_pins.iter_mut().enumerate() {
+            set_pin(pin, (segments >> index) & 1 == 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_pin<P: OutputPin>(pin: &mut P, high: bool) -> Result<(), P::Error> {
+    if high {
+        pin.set_high()
+    } else {
+        pin.set_low()
+    }
+}
+
+/// Multiplexed seven-segment display with `DIGITS` digits (at most 8).
+///
+/// Only one digit is lit at a time; [refresh()](Self::refresh) moves on to
+/// the next one. [multiplex()](Self::multiplex) does so periodically and can
+/// run concurrently with code changing the displayed content.
+///
+/// Example usage (four digits behind two chained 74HC595):
+/// ```no_run
+/// # use libtock::electronics::seven_segment::Polarity;
+/// # use libtock::electronics::SevenSegment;
+/// # use libtock::electronics::ShiftRegister;
+/// # use libtock::result::TockResult;
+/// # use libtock::timer::Duration;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut data = gpios.next().unwrap();
+/// let mut clock = gpios.next().unwrap();
+/// let mut latch = gpios.next().unwrap();
+/// let shift_register = ShiftRegister::new(
+///     data.enable_output()?,
+///     clock.enable_output()?,
+///     latch.enable_output()?,
+/// );
+///
+/// let display = SevenSegment::<_, 4>::new(shift_register, Polarity::CommonCathode)?;
+/// display.set_number(-42)?;
+/// display.multiplex(&timer_driver, Duration::from_ms(4)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SevenSegment<O, const DIGITS: usize> {
+    output: RefCell<O>,
+    polarity: Polarity,
+    segment_map: [u8; 8],
+    content: Cell<[u8; DIGITS]>,
+    next_digit: Cell<usize>,
+}
+
+impl<O: SegmentOutput, const DIGITS: usize> SevenSegment<O, DIGITS> {
+    /// Fails if `DIGITS` exceeds the 8 digit lines of [SegmentOutput]
+    pub fn new(output: O, polarity: Polarity) -> Result<SevenSegment<O, DIGITS>, OutOfRangeError> {
+        if DIGITS > 8 {
+            return Err(OutOfRangeError);
+        }
+        Ok(SevenSegment {
+            output: RefCell::new(output),
+            polarity,
+            segment_map: [0, 1, 2, 3, 4, 5, 6, 7],
+            content: Cell::new([0; DIGITS]),
+            next_digit: Cell::new(0),
+        })
+    }
+
+    /// Adapts to displays wired in a different order. `segment_map[i]` is
+    /// the output line of segment i, counting a to g and the decimal point.
+    pub fn with_segment_map(mut self, segment_map: [u8; 8]) -> SevenSegment<O, DIGITS> {
+        self.segment_map = segment_map;
+        self
+    }
+
+    /// The segments of all digits, the leftmost digit first
+    pub fn content(&self) -> [u8; DIGITS] {
+        self.content.get()
+    }
+
+    pub fn clear(&self) {
+        self.content.set([0; DIGITS]);
+    }
+
+    /// Sets the segments of the digit at 0-based position `index` from the
+    /// left
+    pub fn set_segments(&self, index: usize, segments: u8) -> Result<(), OutOfRangeError> {
+        let mut content = self.content.get();
+        *content.get_mut(index).ok_or(OutOfRangeError)? = segments;
+        self.content.set(content);
+        Ok(())
+    }
+
+    pub fn set_decimal_point(&self, index: usize, on: bool) -> Result<(), OutOfRangeError> {
+        let segments = *self.content.get().get(index).ok_or(OutOfRangeError)?;
+        if on {
+            self.set_segments(index, segments | segment::DP)
+        } else {
+            self.set_segments(index, segments & !segment::DP)
+        }
+    }
+
+    /// Shows `text` left aligned. A `.` is shown as the decimal point of the
+    /// preceding character.
+    pub fn set_text(&self, text: &str) -> Result<(), OutOfRangeError> {
+        let mut content = [0; DIGITS];
+        let mut length = 0;
+        for character in text.chars() {
+            if character == '.' && length > 0 && content[length - 1] & segment::DP == 0 {
+                content[length - 1] |= segment::DP;
+                continue;
+            }
+            let segments = if character == '.' {
+                segment::DP
+            } else {
+                encode(character).ok_or(OutOfRangeError)?
+            };
+            *content.get_mut(length).ok_or(OutOfRangeError)? = segments;
+            length += 1;
+        }
+        self.content.set(content);
+        Ok(())
+    }
+
+    /// Shows `number` right aligned in decimal
+    pub fn set_number(&self, number: i32) -> Result<(), OutOfRangeError> {
+        self.set_integer(i64::from(number).abs() as u64, 10, number < 0)
+    }
+
+    /// Shows `number` right aligned in hexadecimal
+    pub fn set_hex(&self, number: u32) -> Result<(), OutOfRangeError> {
+        self.set_integer(u64::from(number), 16, false)
+    }
+
+    fn set_integer(
+        &self,
+        mut number: u64,
+        base: u64,
+        negative: bool,
+    ) -> Result<(), OutOfRangeError> {
+        let mut content = [0; DIGITS];
+        let mut position = DIGITS;
+        loop {
+            position = position.checked_sub(1).ok_or(OutOfRangeError)?;
+            content[position] = HEX_DIGITS[(number % base) as usize];
+            number /= base;
+            if number == 0 {
+                break;
+            }
+        }
+        if negative {
+            position = position.checked_sub(1).ok_or(OutOfRangeError)?;
+            content[position] = segment::G;
+        }
+        self.content.set(content);
+        Ok(())
+    }
+
+    /// Lights the next digit. Has to be called periodically, at least
+    /// `50 * DIGITS` times per second to avoid flicker.
+    pub fn refresh(&self) -> Result<(), O::Error> {
+        let digit = self.next_digit.get() % DIGITS.max(1);
+        self.next_digit.set(digit + 1);
+        let segments = self
+            .content
+            .get()
+            .get(digit)
+            .map_or(0, |&segments| self.map_segments(segments));
+        let digits = if DIGITS > 1 { Some(1 << digit) } else { None };
+        let mut output = self.output.borrow_mut();
+        match self.polarity {
+            Polarity::CommonCathode => output.write(segments, digits.map(|digits| !digits)),
+            Polarity::CommonAnode => output.write(!segments, digits),
+        }
+    }
+
+    /// Refreshes the display every `digit_time`. Only returns on errors.
+    pub async fn multiplex(
+        &self,
+        timer: &ParallelSleepDriver<'_>,
+        digit_time: Duration<usize>,
+    ) -> TockResult<()>
+    where
+        O::Error: Into<TockError>,
+    {
+        loop {
+            self.refresh().map_err(Into::into)?;
+            timer.sleep(digit_time).await?;
+        }
+    }
+
+    fn map_segments(&self, segments: u8) -> u8 {
+        let mut mapped = 0;
+        for (index, &line) in self.segment_map.iter().enumerate() {
+            if (segments >> index) & 1 == 1 {
+                mapped |= 1 << line;
+            }
+        }
+        mapped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::convert::Infallible;
+
+    #[derive(Default)]
+    struct RecordingOutput {
+        writes: Vec<(u8, Option<u8>)>,
+    }
+
+    impl SegmentOutput for RecordingOutput {
+        type Error = Infallible;
+
+        fn write(&mut self, segments: u8, digits: Option<u8>) -> Result<(), Infallible> {
+            self.writes.push((segments, digits));
+            Ok(())
+        }
+    }
+
+    fn display<const DIGITS: usize>(polarity: Polarity) -> SevenSegment<RecordingOutput, DIGITS> {
+        SevenSegment::new(RecordingOutput::default(), polarity)
+            .ok()
+            .unwrap()
+    }
+
+    #[test]
+    pub fn characters_are_encoded() {
+        assert_eq!(encode('8'), Some(0x7F));
+        assert_eq!(encode('a'), Some(0x77));
+        assert_eq!(encode('F'), Some(0x71));
+        assert_eq!(encode('h'), Some(0x74));
+        assert_eq!(encode('-'), Some(segment::G));
+        assert_eq!(encode('W'), None);
+        assert_eq!(encode_digit(16), None);
+    }
+
+    #[test]
+    pub fn numbers_are_right_aligned() {
+        let display = display::<4>(Polarity::CommonCathode);
+        assert!(display.set_number(-42).is_ok());
+        assert_eq!(display.content(), [0x00, 0x40, 0x66, 0x5B]);
+        assert!(display.set_hex(0xBEEF).is_ok());
+        assert_eq!(display.content(), [0x7C, 0x79, 0x79, 0x71]);
+        assert!(display.set_number(-1000).is_err());
+        assert!(display.set_number(0).is_ok());
+        assert_eq!(display.content(), [0x00, 0x00, 0x00, 0x3F]);
+    }
+
+    #[test]
+    pub fn decimal_points_are_merged_into_text() {
+        let display = display::<4>(Polarity::CommonCathode);
+        assert!(display.set_text("1.5.").is_ok());
+        assert_eq!(display.content(), [0x86, 0xED, 0x00, 0x00]);
+        assert!(display.set_text("..").is_ok());
+        assert_eq!(display.content(), [0x80, 0x80, 0x00, 0x00]);
+        assert!(display.set_text("12345").is_err());
+    }
+
+    #[test]
+    pub fn refresh_cycles_through_digits() {
+        let display = display::<2>(Polarity::CommonCathode);
+        assert!(display.set_text("12").is_ok());
+        for _ in 0..3 {
+            display.refresh().unwrap();
+        }
+        assert_eq!(
+            display.output.borrow().writes,
+            [(0x06, Some(0xFE)), (0x5B, Some(0xFD)), (0x06, Some(0xFE))]
+        );
+    }
+
+    #[test]
+    pub fn more_than_8_digits_are_rejected() {
+        assert!(
+            SevenSegment::<_, 8>::new(RecordingOutput::default(), Polarity::CommonAnode).is_ok()
+        );
+        assert!(
+            SevenSegment::<_, 9>::new(RecordingOutput::default(), Polarity::CommonAnode).is_err()
+        );
+    }
+
+    #[test]
+    pub fn common_anode_inverts_segments() {
+        let display = display::<1>(Polarity::CommonAnode);
+        assert!(display.set_text("1").is_ok());
+        display.refresh().unwrap();
+        assert_eq!(display.output.borrow().writes, [(!0x06, None)]);
+    }
+
+    #[test]
+    pub fn segment_map_reorders_lines() {
+        let display =
+            display::<1>(Polarity::CommonCathode).with_segment_map([7, 6, 5, 4, 3, 2, 1, 0]);
+        assert!(display.set_segments(0, segment::A | segment::DP).is_ok());
+        display.refresh().unwrap();
+        assert_eq!(display.output.borrow().writes, [(0x81, None)]);
+        assert!(display.set_segments(0, segment::B).is_ok());
+        display.refresh().unwrap();
+        assert_eq!(display.output.borrow().writes[1], (0x40, None));
+    }
+}
@@ -1,33 +1,109 @@
-use crate::gpio::GpioWrite;
-use crate::result::TockResult;
+use crate::gpio::BitOrder;
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::digital::v2::OutputPin;
 
-pub struct ShiftRegister<'a> {
-    data_pin: &'a GpioWrite<'a>,
-    clock_pin: &'a GpioWrite<'a>,
-    latch_pin: &'a GpioWrite<'a>,
+/// Serial-in, parallel-out shift registers like the 74HC595, optionally
+/// daisy-chained.
+///
+/// Works with any [OutputPin], e.g. [GpioWrite](crate::gpio::GpioWrite) or
+/// `&GpioWrite`.
+///
+/// Example usage (two chained 74HC595):
+/// ```no_run
+/// # use libtock::electronics::ShiftRegister;
+/// # use libtock::result::TockResult;
+/// # fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut data = gpios.next().unwrap();
+/// let mut clock = gpios.next().unwrap();
+/// let mut latch = gpios.next().unwrap();
+/// let mut output_enable = gpios.next().unwrap();
+/// let mut shift_register = ShiftRegister::new(
+///     data.enable_output()?,
+///     clock.enable_output()?,
+///     latch.enable_output()?,
+/// )
+/// .with_output_enable(output_enable.enable_output()?);
+///
+/// shift_register.write_bytes(&[0x0F, 0xF0])?;
+/// shift_register.set_outputs_enabled(true)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ShiftRegister<P> {
+    data_pin: P,
+    clock_pin: P,
+    latch_pin: P,
+    output_enable_pin: Option<P>,
+    bit_order: BitOrder,
 }
 
-impl<'a> ShiftRegister<'a> {
-    pub fn new(
-        data_pin: &'a GpioWrite<'a>,
-        clock_pin: &'a GpioWrite<'a>,
-        latch_pin: &'a GpioWrite<'a>,
-    ) -> ShiftRegister<'a> {
+impl<P: OutputPin> ShiftRegister<P> {
+    pub fn new(data_pin: P, clock_pin: P, latch_pin: P) -> ShiftRegister<P> {
         ShiftRegister {
             data_pin,
             clock_pin,
             latch_pin,
+            output_enable_pin: None,
+            bit_order: BitOrder::MsbFirst,
         }
     }
 
-    pub fn write_bits(&mut self, values: &[bool]) -> TockResult<()> {
+    /// Adds a pin connected to the active low output enable input. Its level
+    /// is not changed until [set_outputs_enabled()](Self::set_outputs_enabled)
+    /// is called.
+    pub fn with_output_enable(mut self, output_enable_pin: P) -> ShiftRegister<P> {
+        self.output_enable_pin = Some(output_enable_pin);
+        self
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    /// Sets the order in which [write_bytes()](Self::write_bytes) shifts out
+    /// the bits of each byte. With [BitOrder::MsbFirst] bit 7 ends up at
+    /// output Q7.
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+    }
+
+    /// Switches the outputs on or off. Does nothing if there is no output
+    /// enable pin.
+    pub fn set_outputs_enabled(&mut self, enabled: bool) -> Result<(), P::Error> {
+        match self.output_enable_pin {
+            Some(ref mut pin) if enabled => pin.set_low(),
+            Some(ref mut pin) => pin.set_high(),
+            None => Ok(()),
+        }
+    }
+
+    /// Shifts out `values` in the given order and latches them
+    pub fn write_bits(&mut self, values: &[bool]) -> Result<(), P::Error> {
         for i in values {
             self.push_bit(*i)?;
         }
         self.display()
     }
 
-    fn push_bit(&mut self, value: bool) -> TockResult<()> {
+    /// Shifts out `bytes` and latches them. In a chain of registers, the
+    /// first byte ends up in the register furthest from the data pin.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), P::Error> {
+        for &byte in bytes {
+            for bit_num in 0..8 {
+                let shift = match self.bit_order {
+                    BitOrder::LsbFirst => bit_num,
+                    BitOrder::MsbFirst => 7 - bit_num,
+                };
+                self.push_bit((byte >> shift) & 1 == 1)?;
+            }
+        }
+        self.display()
+    }
+
+    fn push_bit(&mut self, value: bool) -> Result<(), P::Error> {
         if value {
             self.data_pin.set_high()
         } else {
@@ -37,8 +113,190 @@ impl<'a> ShiftRegister<'a> {
         self.clock_pin.set_low()
     }
 
-    fn display(&mut self) -> TockResult<()> {
+    fn display(&mut self) -> Result<(), P::Error> {
         self.latch_pin.set_high()?;
         self.latch_pin.set_low()
     }
 }
+
+/// Parallel-in, serial-out shift registers like the 74HC165, optionally
+/// daisy-chained.
+///
+/// The load pin is connected to the active low SH/LD input and the data pin
+/// to the QH output of the register closest to the microcontroller.
+pub struct InputShiftRegister<P, I> {
+    data_pin: I,
+    clock_pin: P,
+    load_pin: P,
+    bit_order: BitOrder,
+}
+
+impl<P: OutputPin, I: InputPin<Error = P::Error>> InputShiftRegister<P, I> {
+    /// Creates the reader and moves the clock and load pins to their idle
+    /// levels
+    pub fn new(
+        data_pin: I,
+        mut clock_pin: P,
+        mut load_pin: P,
+    ) -> Result<InputShiftRegister<P, I>, P::Error> {
+        clock_pin.set_low()?;
+        load_pin.set_high()?;
+        Ok(InputShiftRegister {
+            data_pin,
+            clock_pin,
+            load_pin,
+            bit_order: BitOrder::MsbFirst,
+        })
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    /// Sets the order in which the bits of each byte are shifted in. With
+    /// [BitOrder::MsbFirst] input H ends up in bit 7.
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+    }
+
+    /// Samples all inputs and shifts them into `buffer`. In a chain of
+    /// registers, the first byte is read from the register furthest from
+    /// the data pin.
+    pub fn read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), P::Error> {
+        self.load_pin.set_low()?;
+        self.load_pin.set_high()?;
+        for byte in buffer.iter_mut().rev() {
+            *byte = 0;
+            for bit_num in 0..8 {
+                let shift = match self.bit_order {
+                    BitOrder::LsbFirst => bit_num,
+                    BitOrder::MsbFirst => 7 - bit_num,
+                };
+                if self.data_pin.is_high()? {
+                    *byte |= 1 << shift;
+                }
+                self.clock_pin.set_high()?;
+                self.clock_pin.set_low()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::electronics::test_pin::pin;
+    use crate::result::TockError;
+    use crate::result::TockResult;
+    use core::cell::RefCell;
+
+    const DATA: usize = 0;
+    const CLOCK: usize = 1;
+    const LATCH: usize = 2;
+    const OUTPUT_ENABLE: usize = 3;
+    const LOAD: usize = 4;
+
+    /// Simulates a chain of 74HC595 and returns the latched outputs, the
+    /// register closest to the data pin last.
+    fn latched(log: &[(usize, bool)], registers: usize) -> Vec<u8> {
+        let mut data = false;
+        let mut shift = vec![0u8; registers];
+        let mut latched = vec![0u8; registers];
+        for &(id, high) in log {
+            match (id, high) {
+                (DATA, _) => data = high,
+                (CLOCK, true) => {
+                    for index in 0..registers {
+                        let carry_in = if index + 1 < registers {
+                            shift[index + 1] >> 7 == 1
+                        } else {
+                            data
+                        };
+                        shift[index] = (shift[index] << 1) | carry_in as u8;
+                    }
+                }
+                (LATCH, true) => latched.copy_from_slice(&shift),
+                _ => {}
+            }
+        }
+        latched
+    }
+
+    #[test]
+    pub fn chained_registers_receive_bytes_in_order() {
+        let log = RefCell::new(Vec::new());
+        let mut shift_register =
+            ShiftRegister::new(pin(DATA, &log), pin(CLOCK, &log), pin(LATCH, &log));
+        shift_register.write_bytes(&[0x12, 0xA5]).unwrap();
+        assert_eq!(latched(&log.borrow(), 2), [0x12, 0xA5]);
+    }
+
+    #[test]
+    pub fn lsb_first_reverses_bits() {
+        let log = RefCell::new(Vec::new());
+        let mut shift_register =
+            ShiftRegister::new(pin(DATA, &log), pin(CLOCK, &log), pin(LATCH, &log));
+        shift_register.set_bit_order(BitOrder::LsbFirst);
+        shift_register.write_bytes(&[0x01]).unwrap();
+        assert_eq!(latched(&log.borrow(), 1), [0x80]);
+    }
+
+    #[test]
+    pub fn output_enable_is_active_low() {
+        let log = RefCell::new(Vec::new());
+        let mut shift_register =
+            ShiftRegister::new(pin(DATA, &log), pin(CLOCK, &log), pin(LATCH, &log))
+                .with_output_enable(pin(OUTPUT_ENABLE, &log));
+        assert!(log.borrow().is_empty());
+        shift_register.set_outputs_enabled(true).unwrap();
+        shift_register.set_outputs_enabled(false).unwrap();
+        assert_eq!(
+            *log.borrow(),
+            [(OUTPUT_ENABLE, false), (OUTPUT_ENABLE, true)]
+        );
+    }
+
+    /// Simulates a chain of 74HC165 with the given parallel inputs, the
+    /// register closest to the data pin last.
+    struct SimulatedInputs<'a> {
+        log: &'a RefCell<Vec<(usize, bool)>>,
+        inputs: Vec<u8>,
+    }
+
+    impl<'a> InputPin for SimulatedInputs<'a> {
+        type Error = TockError;
+
+        fn is_high(&self) -> TockResult<bool> {
+            let log = self.log.borrow();
+            let loaded = log
+                .iter()
+                .rposition(|&entry| entry == (LOAD, false))
+                .expect("inputs were not loaded");
+            let shifted = log[loaded..]
+                .iter()
+                .filter(|&&entry| entry == (CLOCK, true))
+                .count();
+            let register = self.inputs.len() - 1 - shifted / 8;
+            Ok((self.inputs[register] >> (7 - shifted % 8)) & 1 == 1)
+        }
+
+        fn is_low(&self) -> TockResult<bool> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    #[test]
+    pub fn input_registers_are_read_in_chain_order() {
+        let log = RefCell::new(Vec::new());
+        let inputs = SimulatedInputs {
+            log: &log,
+            inputs: vec![0x3C, 0x81],
+        };
+        let mut reader =
+            InputShiftRegister::new(inputs, pin(CLOCK, &log), pin(LOAD, &log)).unwrap();
+        let mut buffer = [0; 2];
+        reader.read_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x3C, 0x81]);
+    }
+}
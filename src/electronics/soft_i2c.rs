@@ -0,0 +1,398 @@
+use crate::gpio::GpioOpenDrain;
+use crate::gpio::GpioState;
+use crate::result::OtherError;
+use crate::result::TockError;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+use embedded_hal::blocking::i2c;
+
+/// Bit-banged I2C master using two GPIO pins.
+///
+/// Both lines are emulated as open-drain outputs, see
+/// [Gpio::enable_open_drain()](crate::gpio::Gpio::enable_open_drain), so the
+/// bus needs pull-up resistors (the internal ones may be sufficient for
+/// short lines). Addresses are 7 bit.
+///
+/// Example usage:
+/// ```no_run
+/// # use embedded_hal::blocking::i2c::WriteRead;
+/// # use libtock::electronics::SoftI2c;
+/// # use libtock::result::TockResult;
+/// # fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut scl = gpios.next().unwrap();
+/// let mut sda = gpios.next().unwrap();
+/// let mut i2c = SoftI2c::new(
+///     scl.enable_open_drain()?,
+///     sda.enable_open_drain()?,
+///     &timer_driver,
+/// )?;
+///
+/// let mut id = [0];
+/// i2c.write_read(0x76, &[0xD0], &mut id)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SoftI2c<'a> {
+    scl: GpioOpenDrain<'a>,
+    sda: GpioOpenDrain<'a>,
+    timer: &'a ParallelSleepDriver<'a>,
+    clock_frequency: usize,
+    stretch_timeout_ticks: Option<usize>,
+    started: bool,
+}
+
+/// Default time a slave may hold the clock line low
+pub const DEFAULT_CLOCK_STRETCH_TIMEOUT: Duration<usize> = Duration::from_ms(10);
+
+impl<'a> SoftI2c<'a> {
+    /// Creates the I2C master and releases both lines
+    pub fn new(
+        scl: GpioOpenDrain<'a>,
+        sda: GpioOpenDrain<'a>,
+        timer: &'a ParallelSleepDriver<'a>,
+    ) -> TockResult<SoftI2c<'a>> {
+        let clock_frequency = timer.get_current_clock()?.clock_frequency().hz();
+        scl.release()?;
+        sda.release()?;
+        Ok(SoftI2c {
+            scl,
+            sda,
+            timer,
+            clock_frequency,
+            stretch_timeout_ticks: Some(timer::ms_to_ticks(
+                DEFAULT_CLOCK_STRETCH_TIMEOUT.ms(),
+                clock_frequency,
+            )),
+            started: false,
+        })
+    }
+
+    /// Sets the maximum time a slave may hold the clock line low before the
+    /// transfer fails. `None` disables clock stretching support: the clock
+    /// line is not read back, which saves one system call per bit.
+    pub fn set_clock_stretch_timeout(&mut self, timeout: Option<Duration<usize>>) {
+        self.stretch_timeout_ticks =
+            timeout.map(|timeout| timer::ms_to_ticks(timeout.ms(), self.clock_frequency));
+    }
+
+    /// Generates a start condition, or a repeated start condition if a
+    /// transfer is already in progress.
+    pub fn start(&mut self) -> TockResult<()> {
+        if self.started {
+            self.sda.release()?;
+            self.release_clock()?;
+        }
+        self.sda.set_low()?;
+        self.scl.set_low()?;
+        self.started = true;
+        Ok(())
+    }
+
+    /// Generates a stop condition and releases the bus. The data line is
+    /// released even if the clock line is stuck low.
+    pub fn stop(&mut self) -> TockResult<()> {
+        self.sda.set_low()?;
+        let result = self.release_clock();
+        self.sda.release()?;
+        self.started = false;
+        result
+    }
+
+    /// Sends `byte` and returns whether the slave acknowledged it
+    pub fn write_byte(&mut self, byte: u8) -> TockResult<bool> {
+        for shift in (0..8).rev() {
+            self.write_bit((byte >> shift) & 1 == 1)?;
+        }
+        Ok(!self.read_bit()?)
+    }
+
+    /// Receives a byte. `ack` should be `false` for the last byte of a read
+    /// to signal the slave to stop sending.
+    pub fn read_byte(&mut self, ack: bool) -> TockResult<u8> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = (byte << 1) | self.read_bit()? as u8;
+        }
+        self.write_bit(!ack)?;
+        Ok(byte)
+    }
+
+    fn write_bit(&self, bit: bool) -> TockResult<()> {
+        if bit {
+            self.sda.release()?;
+        } else {
+            self.sda.set_low()?;
+        }
+        self.release_clock()?;
+        self.scl.set_low()
+    }
+
+    fn read_bit(&self) -> TockResult<bool> {
+        self.sda.release()?;
+        self.release_clock()?;
+        let bit = self.sda.read()? == GpioState::High;
+        self.scl.set_low()?;
+        Ok(bit)
+    }
+
+    /// Releases the clock line and waits for slaves stretching the clock
+    fn release_clock(&self) -> TockResult<()> {
+        self.scl.release()?;
+        let timeout_ticks = match self.stretch_timeout_ticks {
+            Some(timeout_ticks) => timeout_ticks,
+            None => return Ok(()),
+        };
+        if self.scl.read()? == GpioState::High {
+            return Ok(());
+        }
+        let start = self.timer.get_current_clock()?.num_ticks() as usize;
+        loop {
+            if self.scl.read()? == GpioState::High {
+                return Ok(());
+            }
+            let now = self.timer.get_current_clock()?.num_ticks() as usize;
+            if now.wrapping_sub(start) >= timeout_ticks {
+                return Err(OtherError::I2cClockStretchTimeout.into());
+            }
+        }
+    }
+
+    fn address(&mut self, address: u8, read: bool) -> TockResult<()> {
+        self.start()?;
+        if self.write_byte((address << 1) | read as u8)? {
+            Ok(())
+        } else {
+            Err(OtherError::I2cAddressNotAcknowledged.into())
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> TockResult<()> {
+        for &byte in bytes {
+            if !self.write_byte(byte)? {
+                return Err(OtherError::I2cDataNotAcknowledged.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, buffer: &mut [u8]) -> TockResult<()> {
+        let last = buffer.len().saturating_sub(1);
+        for (index, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_byte(index != last)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `transaction` and always finishes with a stop condition, so a
+    /// failed transfer does not leave the bus busy.
+    fn transaction<F>(&mut self, transaction: F) -> TockResult<()>
+    where
+        F: FnOnce(&mut Self) -> TockResult<()>,
+    {
+        let result = transaction(self);
+        let stop_result = self.stop();
+        result.and(stop_result)
+    }
+}
+
+impl<'a> i2c::Write for SoftI2c<'a> {
+    type Error = TockError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.transaction(|i2c| {
+            i2c.address(address, false)?;
+            i2c.write_bytes(bytes)
+        })
+    }
+}
+
+impl<'a> i2c::Read for SoftI2c<'a> {
+    type Error = TockError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.transaction(|i2c| {
+            i2c.address(address, true)?;
+            i2c.read_bytes(buffer)
+        })
+    }
+}
+
+impl<'a> i2c::WriteRead for SoftI2c<'a> {
+    type Error = TockError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.transaction(|i2c| {
+            i2c.address(address, false)?;
+            i2c.write_bytes(bytes)?;
+            i2c.address(address, true)?;
+            i2c.read_bytes(buffer)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpio::command_nr;
+    use crate::gpio::DRIVER_NUMBER;
+    use crate::syscalls;
+    use crate::syscalls::raw::Event;
+    use core::cell::Cell;
+    use embedded_hal::blocking::i2c::Read;
+    use embedded_hal::blocking::i2c::Write;
+    use embedded_hal::blocking::i2c::WriteRead;
+
+    const SCL: usize = 0;
+    const SDA: usize = 1;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Symbol {
+        Start,
+        Stop,
+        Bit(bool),
+    }
+
+    fn record_i2c<F>(
+        line_level: isize,
+        stretch_timeout: Option<Duration<usize>>,
+        f: F,
+    ) -> Vec<Event>
+    where
+        F: Fn(&mut SoftI2c) -> TockResult<()>,
+    {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            next_return.set(2);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            let mut gpios = gpio_driver.gpios();
+            let mut scl = gpios.next().unwrap();
+            let mut sda = gpios.next().unwrap();
+            next_return.set(1000);
+            let mut i2c = SoftI2c::new(
+                scl.enable_open_drain()?,
+                sda.enable_open_drain()?,
+                &timer_driver,
+            )?;
+            i2c.set_clock_stretch_timeout(stretch_timeout);
+            next_return.set(line_level);
+            f(&mut i2c)
+        })
+    }
+
+    /// Replays the recorded GPIO commands and decodes the bus conditions as
+    /// seen by a slave. A line is low while its pin is in output mode. A bit
+    /// is only complete when the clock falls without a start or stop
+    /// condition in between.
+    fn decode(events: &[Event]) -> Vec<Symbol> {
+        let mut high = [true; 2];
+        let mut bit = None;
+        let mut symbols = Vec::new();
+        for event in events {
+            let (pin, level) = match *event {
+                Event::Command(DRIVER_NUMBER, command_nr::ENABLE_OUTPUT, pin, _) => (pin, false),
+                Event::Command(DRIVER_NUMBER, command_nr::ENABLE_INPUT, pin, _) => (pin, true),
+                _ => continue,
+            };
+            if high[pin] == level {
+                continue;
+            }
+            high[pin] = level;
+            if pin == SCL && level {
+                bit = Some(high[SDA]);
+            } else if pin == SCL {
+                symbols.extend(bit.take().map(Symbol::Bit));
+            } else if high[SCL] {
+                bit = None;
+                symbols.push(if level { Symbol::Stop } else { Symbol::Start });
+            }
+        }
+        assert_eq!(high, [true, true], "bus must be released");
+        symbols
+    }
+
+    fn bits_of(byte: u8) -> impl Iterator<Item = Symbol> {
+        (0..8)
+            .rev()
+            .map(move |shift| Symbol::Bit((byte >> shift) & 1 == 1))
+    }
+
+    #[test]
+    pub fn write_sends_address_and_data() {
+        let events = record_i2c(0, None, |i2c| i2c.write(0x50, &[0xA5]));
+        let mut expected = vec![Symbol::Start];
+        expected.extend(bits_of(0xA0));
+        expected.push(Symbol::Bit(true));
+        expected.extend(bits_of(0xA5));
+        expected.push(Symbol::Bit(true));
+        expected.push(Symbol::Stop);
+        assert_eq!(decode(&events), expected);
+    }
+
+    #[test]
+    pub fn write_read_uses_repeated_start_and_nacks_last_byte() {
+        let events = record_i2c(0, None, |i2c| {
+            let mut buffer = [0xFF; 2];
+            i2c.write_read(0x50, &[0x10], &mut buffer)?;
+            assert_eq!(buffer, [0x00, 0x00]);
+            Ok(())
+        });
+        let mut expected = vec![Symbol::Start];
+        expected.extend(bits_of(0xA0));
+        expected.push(Symbol::Bit(true));
+        expected.extend(bits_of(0x10));
+        expected.push(Symbol::Bit(true));
+        expected.push(Symbol::Start);
+        expected.extend(bits_of(0xA1));
+        expected.push(Symbol::Bit(true));
+        expected.extend(bits_of(0xFF));
+        expected.push(Symbol::Bit(false));
+        expected.extend(bits_of(0xFF));
+        expected.push(Symbol::Bit(true));
+        expected.push(Symbol::Stop);
+        assert_eq!(decode(&events), expected);
+    }
+
+    #[test]
+    pub fn missing_acknowledge_aborts_transfer() {
+        let events = record_i2c(1, None, |i2c| {
+            assert!(matches!(
+                i2c.write(0x50, &[0xA5, 0x5A]),
+                Err(TockError::Other(OtherError::I2cAddressNotAcknowledged))
+            ));
+            Ok(())
+        });
+        let mut expected = vec![Symbol::Start];
+        expected.extend(bits_of(0xA0));
+        expected.push(Symbol::Bit(true));
+        expected.push(Symbol::Stop);
+        assert_eq!(decode(&events), expected);
+    }
+
+    #[test]
+    pub fn clock_held_low_times_out() {
+        let timed_out = Cell::new(false);
+        record_i2c(0, Some(Duration::from_ms(0)), |i2c| {
+            let mut buffer = [0];
+            timed_out.set(matches!(
+                i2c.read(0x50, &mut buffer),
+                Err(TockError::Other(OtherError::I2cClockStretchTimeout))
+            ));
+            Ok(())
+        });
+        assert!(timed_out.get());
+    }
+}
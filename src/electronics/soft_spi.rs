@@ -0,0 +1,316 @@
+use crate::gpio::BitOrder;
+use crate::gpio::GpioRead;
+use crate::gpio::GpioState;
+use crate::gpio::GpioWrite;
+use crate::result::OutOfRangeError;
+use crate::result::TockError;
+use crate::result::TockResult;
+use embedded_hal::blocking::spi;
+use embedded_hal::spi::Mode;
+use embedded_hal::spi::Phase;
+use embedded_hal::spi::Polarity;
+
+/// Bit-banged SPI master using GPIO pins.
+///
+/// The chip select lines are active low. `CS` is the number of devices on
+/// the bus; a device has to be selected with [SoftSpi::select()] before
+/// transferring data.
+///
+/// Example usage:
+/// ```no_run
+/// # use embedded_hal::blocking::spi::Transfer;
+/// # use embedded_hal::spi::MODE_0;
+/// # use libtock::electronics::SoftSpi;
+/// # use libtock::gpio::BitOrder;
+/// # use libtock::gpio::ResistorMode;
+/// # use libtock::result::TockResult;
+/// # fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut sck = gpios.next().unwrap();
+/// let mut mosi = gpios.next().unwrap();
+/// let mut miso = gpios.next().unwrap();
+/// let mut cs = gpios.next().unwrap();
+/// let mut spi = SoftSpi::new(
+///     sck.enable_output()?,
+///     mosi.enable_output()?,
+///     miso.enable_input(ResistorMode::PullNone)?,
+///     [cs.enable_output()?],
+///     MODE_0,
+///     BitOrder::MsbFirst,
+/// )?;
+///
+/// let mut buffer = [0x9F, 0, 0, 0];
+/// spi.select(0)?;
+/// spi.transfer(&mut buffer)?;
+/// spi.deselect()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SoftSpi<'a, const CS: usize> {
+    sck: GpioWrite<'a>,
+    mosi: GpioWrite<'a>,
+    miso: GpioRead<'a>,
+    chip_selects: [GpioWrite<'a>; CS],
+    mode: Mode,
+    bit_order: BitOrder,
+}
+
+impl<'a, const CS: usize> SoftSpi<'a, CS> {
+    /// Creates the SPI master and moves the clock to its idle level and all
+    /// chip select lines to high.
+    pub fn new(
+        sck: GpioWrite<'a>,
+        mosi: GpioWrite<'a>,
+        miso: GpioRead<'a>,
+        chip_selects: [GpioWrite<'a>; CS],
+        mode: Mode,
+        bit_order: BitOrder,
+    ) -> TockResult<SoftSpi<'a, CS>> {
+        let spi = SoftSpi {
+            sck,
+            mosi,
+            miso,
+            chip_selects,
+            mode,
+            bit_order,
+        };
+        spi.deselect()?;
+        spi.set_clock(false)?;
+        Ok(spi)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Changes the SPI mode and moves the clock to its new idle level
+    pub fn set_mode(&mut self, mode: Mode) -> TockResult<()> {
+        self.mode = mode;
+        self.set_clock(false)
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+    }
+
+    /// Selects the device at 0-based index `device` and deselects all others
+    pub fn select(&self, device: usize) -> TockResult<()> {
+        if device >= CS {
+            return Err(OutOfRangeError.into());
+        }
+        for (index, chip_select) in self.chip_selects.iter().enumerate() {
+            if index != device {
+                chip_select.set_high()?;
+            }
+        }
+        self.chip_selects[device].set_low()
+    }
+
+    /// Deselects all devices
+    pub fn deselect(&self) -> TockResult<()> {
+        for chip_select in self.chip_selects.iter() {
+            chip_select.set_high()?;
+        }
+        Ok(())
+    }
+
+    /// Sends `byte` and returns the byte received at the same time
+    pub fn transfer_byte(&self, byte: u8) -> TockResult<u8> {
+        self.exchange(byte, true)
+    }
+
+    /// Sends `byte` without sampling the input line
+    pub fn write_byte(&self, byte: u8) -> TockResult<()> {
+        self.exchange(byte, false)?;
+        Ok(())
+    }
+
+    fn exchange(&self, output: u8, sample_input: bool) -> TockResult<u8> {
+        let mut input = 0;
+        for bit_num in 0..8 {
+            let shift = match self.bit_order {
+                BitOrder::LsbFirst => bit_num,
+                BitOrder::MsbFirst => 7 - bit_num,
+            };
+            let bit = (output >> shift) & 1 == 1;
+            let received = match self.mode.phase {
+                Phase::CaptureOnFirstTransition => {
+                    self.set_data(bit)?;
+                    self.set_clock(true)?;
+                    let received = sample_input && self.miso.read()? == GpioState::High;
+                    self.set_clock(false)?;
+                    received
+                }
+                Phase::CaptureOnSecondTransition => {
+                    self.set_clock(true)?;
+                    self.set_data(bit)?;
+                    self.set_clock(false)?;
+                    sample_input && self.miso.read()? == GpioState::High
+                }
+            };
+            if received {
+                input |= 1 << shift;
+            }
+        }
+        Ok(input)
+    }
+
+    fn set_data(&self, high: bool) -> TockResult<()> {
+        if high {
+            self.mosi.set_high()
+        } else {
+            self.mosi.set_low()
+        }
+    }
+
+    fn set_clock(&self, active: bool) -> TockResult<()> {
+        if active == (self.mode.polarity == Polarity::IdleLow) {
+            self.sck.set_high()
+        } else {
+            self.sck.set_low()
+        }
+    }
+}
+
+impl<'a, const CS: usize> spi::Transfer<u8> for SoftSpi<'a, CS> {
+    type Error = TockError;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        for word in words.iter_mut() {
+            *word = self.transfer_byte(*word)?;
+        }
+        Ok(words)
+    }
+}
+
+impl<'a, const CS: usize> spi::Write<u8> for SoftSpi<'a, CS> {
+    type Error = TockError;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        for word in words {
+            self.write_byte(*word)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpio::command_nr;
+    use crate::gpio::ResistorMode;
+    use crate::gpio::DRIVER_NUMBER;
+    use crate::syscalls;
+    use crate::syscalls::raw::Event;
+    use embedded_hal::blocking::spi::Transfer;
+    use embedded_hal::blocking::spi::Write;
+    use embedded_hal::spi::MODE_0;
+    use embedded_hal::spi::MODE_1;
+    use embedded_hal::spi::MODE_2;
+    use embedded_hal::spi::MODE_3;
+
+    const SCK: usize = 0;
+    const MOSI: usize = 1;
+    const CS: usize = 3;
+
+    fn record_spi<F>(mode: Mode, bit_order: BitOrder, miso_level: isize, f: F) -> Vec<Event>
+    where
+        F: Fn(&mut SoftSpi<1>) -> TockResult<()>,
+    {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(4);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            next_return.set(miso_level);
+            let mut gpios = gpio_driver.gpios();
+            let mut sck = gpios.next().unwrap();
+            let mut mosi = gpios.next().unwrap();
+            let mut miso = gpios.next().unwrap();
+            let mut cs = gpios.next().unwrap();
+            let mut spi = SoftSpi::new(
+                sck.enable_output()?,
+                mosi.enable_output()?,
+                miso.enable_input(ResistorMode::PullNone)?,
+                [cs.enable_output()?],
+                mode,
+                bit_order,
+            )?;
+            f(&mut spi)
+        })
+    }
+
+    /// Replays the recorded GPIO commands and returns the MOSI level at each
+    /// capturing clock edge while the chip select line is low.
+    fn captured_bits(events: &[Event], mode: Mode) -> Vec<bool> {
+        let idle_high = mode.polarity == Polarity::IdleHigh;
+        let capture_on_rising = idle_high == (mode.phase == Phase::CaptureOnSecondTransition);
+        let mut levels = [false; 4];
+        let mut bits = Vec::new();
+        for event in events {
+            let (pin, high) = match *event {
+                Event::Command(DRIVER_NUMBER, command_nr::SET_HIGH, pin, _) => (pin, true),
+                Event::Command(DRIVER_NUMBER, command_nr::SET_LOW, pin, _) => (pin, false),
+                _ => continue,
+            };
+            let is_edge = levels[pin] != high;
+            levels[pin] = high;
+            if pin == SCK && is_edge && high == capture_on_rising && !levels[CS] {
+                bits.push(levels[MOSI]);
+            }
+        }
+        assert_eq!(levels[SCK], idle_high, "clock must return to idle");
+        bits
+    }
+
+    fn bits_of(byte: u8) -> Vec<bool> {
+        (0..8).rev().map(|shift| (byte >> shift) & 1 == 1).collect()
+    }
+
+    #[test]
+    pub fn waveform_is_correct_in_all_modes() {
+        for &mode in &[MODE_0, MODE_1, MODE_2, MODE_3] {
+            let events = record_spi(mode, BitOrder::MsbFirst, 0, |spi| {
+                spi.select(0)?;
+                spi.write(&[0xA5, 0x3C])?;
+                spi.deselect()
+            });
+            let expected: Vec<bool> = bits_of(0xA5).into_iter().chain(bits_of(0x3C)).collect();
+            assert_eq!(captured_bits(&events, mode), expected);
+        }
+    }
+
+    #[test]
+    pub fn lsb_first_reverses_bit_order() {
+        let events = record_spi(MODE_0, BitOrder::LsbFirst, 0, |spi| {
+            spi.select(0)?;
+            spi.write(&[0x01])?;
+            spi.deselect()
+        });
+        assert_eq!(captured_bits(&events, MODE_0), bits_of(0x80));
+    }
+
+    #[test]
+    pub fn transfer_reads_input_line() {
+        for &(miso_level, expected) in &[(0, 0x00), (1, 0xFF)] {
+            record_spi(MODE_1, BitOrder::MsbFirst, miso_level, |spi| {
+                let mut buffer = [0x12, 0x34];
+                assert_eq!(spi.transfer(&mut buffer)?, &[expected, expected]);
+                Ok(())
+            });
+        }
+    }
+
+    #[test]
+    pub fn selecting_unknown_device_fails() {
+        record_spi(MODE_0, BitOrder::MsbFirst, 0, |spi| {
+            assert!(spi.select(1).is_err());
+            Ok(())
+        });
+    }
+}
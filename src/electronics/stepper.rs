@@ -0,0 +1,456 @@
+use crate::dsp;
+use crate::result::OutOfRangeError;
+use crate::result::TockError;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Coil patterns energizing one coil at a time, bit `n` drives pin `n`
+const WAVE: [u8; 4] = [0b0001, 0b0010, 0b0100, 0b1000];
+/// Coil patterns energizing two adjacent coils at a time
+const FULL_STEP: [u8; 4] = [0b0011, 0b0110, 0b1100, 0b1001];
+/// Alternating wave and full step patterns
+const HALF_STEP: [u8; 8] = [
+    0b0001, 0b0011, 0b0010, 0b0110, 0b0100, 0b1100, 0b1000, 0b1001,
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StepMode {
+    /// One coil at a time: lowest power consumption and torque
+    Wave,
+    /// Two coils at a time: full torque
+    FullStep,
+    /// Alternates between one and two coils: twice the resolution
+    HalfStep,
+}
+
+impl StepMode {
+    fn patterns(self) -> &'static [u8] {
+        match self {
+            StepMode::Wave => &WAVE,
+            StepMode::FullStep => &FULL_STEP,
+            StepMode::HalfStep => &HALF_STEP,
+        }
+    }
+}
+
+/// Hardware that moves a motor by single steps, see [UnipolarCoils] and
+/// [StepDir].
+pub trait StepperOutput {
+    /// Moves one step forward or backward
+    fn step(&mut self, forward: bool) -> TockResult<()>;
+
+    /// Stops holding the current position, e.g. to save power
+    fn release(&mut self) -> TockResult<()>;
+}
+
+/// Unipolar stepper motor with four coils driven through a transistor array
+/// like the ULN2003. The pins are connected to the coils in the order in
+/// which they are energized.
+pub struct UnipolarCoils<P> {
+    pins: [P; 4],
+    mode: StepMode,
+    phase: usize,
+}
+
+impl<P: OutputPin> UnipolarCoils<P>
+where
+    P::Error: Into<TockError>,
+{
+    pub fn new(pins: [P; 4], mode: StepMode) -> UnipolarCoils<P> {
+        UnipolarCoils {
+            pins,
+            mode,
+            phase: 0,
+        }
+    }
+
+    pub fn mode(&self) -> StepMode {
+        self.mode
+    }
+
+    fn write_pattern(&mut self, pattern: u8) -> TockResult<()> {
+        for (index, pin) in self.pins.iter_mut().enumerate() {
+            set_pin(pin, pattern & (1 << index) != 0)?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: OutputPin> StepperOutput for UnipolarCoils<P>
+where
+    P::Error: Into<TockError>,
+{
+    fn step(&mut self, forward: bool) -> TockResult<()> {
+        let patterns = self.mode.patterns();
+        self.phase = if forward {
+            (self.phase + 1) % patterns.len()
+        } else {
+            (self.phase + patterns.len() - 1) % patterns.len()
+        };
+        self.write_pattern(patterns[self.phase])
+    }
+
+    fn release(&mut self) -> TockResult<()> {
+        self.write_pattern(0)
+    }
+}
+
+/// Stepper driver with STEP and DIR inputs like the A4988 or the DRV8825.
+/// Microstepping is configured in hardware.
+pub struct StepDir<P> {
+    step_pin: P,
+    dir_pin: P,
+    enable_pin: Option<P>,
+    direction: Option<bool>,
+    enabled: Option<bool>,
+}
+
+impl<P: OutputPin> StepDir<P>
+where
+    P::Error: Into<TockError>,
+{
+    pub fn new(step_pin: P, dir_pin: P) -> StepDir<P> {
+        StepDir {
+            step_pin,
+            dir_pin,
+            enable_pin: None,
+            direction: None,
+            enabled: None,
+        }
+    }
+
+    /// Adds a pin connected to the active low enable input. The driver is
+    /// enabled before the first step and disabled by
+    /// [release()](StepperOutput::release).
+    pub fn with_enable(mut self, enable_pin: P) -> StepDir<P> {
+        self.enable_pin = Some(enable_pin);
+        self
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> TockResult<()> {
+        if let Some(ref mut pin) = self.enable_pin {
+            if self.enabled != Some(enabled) {
+                set_pin(pin, !enabled)?;
+                self.enabled = Some(enabled);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<P: OutputPin> StepperOutput for StepDir<P>
+where
+    P::Error: Into<TockError>,
+{
+    fn step(&mut self, forward: bool) -> TockResult<()> {
+        self.set_enabled(true)?;
+        if self.direction != Some(forward) {
+            set_pin(&mut self.dir_pin, forward)?;
+            self.direction = Some(forward);
+        }
+        set_pin(&mut self.step_pin, true)?;
+        set_pin(&mut self.step_pin, false)
+    }
+
+    fn release(&mut self) -> TockResult<()> {
+        self.set_enabled(false)
+    }
+}
+
+/// Speed profile with constant acceleration and deceleration, limited to a
+/// maximum speed. An acceleration of 0 moves at full speed right away.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Ramp {
+    max_speed: usize,
+    acceleration: usize,
+}
+
+impl Ramp {
+    /// `max_speed` is in steps per second, `acceleration` in steps per
+    /// second squared
+    pub fn new(max_speed: usize, acceleration: usize) -> Ramp {
+        Ramp {
+            max_speed: max_speed.max(1),
+            acceleration,
+        }
+    }
+
+    pub fn max_speed(&self) -> usize {
+        self.max_speed
+    }
+
+    pub fn acceleration(&self) -> usize {
+        self.acceleration
+    }
+
+    /// Time in microseconds between step `index` and the next one for a move
+    /// of `total` steps
+    pub fn interval_us(&self, index: usize, total: usize) -> usize {
+        let speed = if self.acceleration == 0 {
+            self.max_speed
+        } else {
+            // Reaching speed v takes v² / (2a) steps
+            let ramp_steps = (index + 1).min(total.saturating_sub(index)).max(1);
+            let ramp_speed = dsp::isqrt(
+                ramp_steps
+                    .saturating_mul(2)
+                    .saturating_mul(self.acceleration) as u64,
+            );
+            ramp_speed.min(self.max_speed as u64).max(1) as usize
+        };
+        1_000_000 / speed
+    }
+}
+
+/// Stepper motor that keeps track of its position.
+///
+/// Example usage (A4988 driver):
+/// ```no_run
+/// # use libtock::electronics::stepper::Ramp;
+/// # use libtock::electronics::stepper::StepDir;
+/// # use libtock::electronics::Stepper;
+/// # use libtock::result::TockResult;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut step = gpios.next().unwrap();
+/// let mut dir = gpios.next().unwrap();
+/// let output = StepDir::new(step.enable_output()?, dir.enable_output()?);
+/// let mut stepper = Stepper::new(output, &timer_driver, Ramp::new(800, 1600))?;
+///
+/// stepper.move_to(1600).await?;
+/// stepper.move_to(0).await?;
+/// stepper.release()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Stepper<'a, O> {
+    output: O,
+    timer: &'a ParallelSleepDriver<'a>,
+    ramp: Ramp,
+    position: isize,
+    clock_frequency: usize,
+}
+
+impl<'a, O: StepperOutput> Stepper<'a, O> {
+    /// Creates the stepper at position 0
+    pub fn new(
+        output: O,
+        timer: &'a ParallelSleepDriver<'a>,
+        ramp: Ramp,
+    ) -> TockResult<Stepper<'a, O>> {
+        let clock_frequency = timer.get_current_clock()?.clock_frequency().hz();
+        Ok(Stepper {
+            output,
+            timer,
+            ramp,
+            position: 0,
+            clock_frequency,
+        })
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn ramp(&self) -> Ramp {
+        self.ramp
+    }
+
+    pub fn set_ramp(&mut self, ramp: Ramp) {
+        self.ramp = ramp;
+    }
+
+    pub fn position(&self) -> isize {
+        self.position
+    }
+
+    /// Changes the current position without moving, e.g. after homing
+    pub fn set_position(&mut self, position: isize) {
+        self.position = position;
+    }
+
+    /// Moves a single step without waiting. Fails with [OutOfRangeError]
+    /// without moving if the position would overflow.
+    pub fn step(&mut self, forward: bool) -> TockResult<()> {
+        let position = self
+            .position
+            .checked_add(if forward { 1 } else { -1 })
+            .ok_or(OutOfRangeError)?;
+        self.output.step(forward)?;
+        self.position = position;
+        Ok(())
+    }
+
+    /// Moves by `steps` along the speed profile. Negative values move
+    /// backward. Fails with [OutOfRangeError] without moving if the target
+    /// position would overflow.
+    pub async fn move_by(&mut self, steps: isize) -> TockResult<()> {
+        self.position.checked_add(steps).ok_or(OutOfRangeError)?;
+        let forward = steps > 0;
+        // `wrapping_abs()` keeps the magnitude of `isize::MIN` intact
+        let total = steps.wrapping_abs() as usize;
+        for index in 0..total {
+            self.step(forward)?;
+            self.wait_us(self.ramp.interval_us(index, total)).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves to `position` along the speed profile. Fails with
+    /// [OutOfRangeError] without moving if the distance overflows.
+    pub async fn move_to(&mut self, position: isize) -> TockResult<()> {
+        let steps = position.checked_sub(self.position).ok_or(OutOfRangeError)?;
+        self.move_by(steps).await
+    }
+
+    pub fn release(&mut self) -> TockResult<()> {
+        self.output.release()
+    }
+
+    /// Sleeps for whole milliseconds and busy waits for the rest
+    async fn wait_us(&self, duration_us: usize) -> TockResult<()> {
+        if duration_us >= 1000 {
+            self.timer
+                .sleep(Duration::from_ms(duration_us / 1000))
+                .await?;
+        }
+        let ticks = timer::us_to_ticks(duration_us % 1000, self.clock_frequency);
+        self.timer.busy_wait_ticks(ticks)
+    }
+}
+
+fn set_pin<P: OutputPin>(pin: &mut P, high: bool) -> TockResult<()>
+where
+    P::Error: Into<TockError>,
+{
+    if high { pin.set_high() } else { pin.set_low() }.map_err(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::electronics::test_pin::pin;
+    use crate::electronics::test_pin::TestPin;
+    use crate::executor;
+    use crate::syscalls;
+    use core::cell::RefCell;
+    use core::task::Poll;
+
+    /// Returns the coil pattern after each step
+    fn patterns(coils: &mut UnipolarCoils<TestPin>, moves: &[bool]) -> Vec<u8> {
+        moves
+            .iter()
+            .map(|&forward| {
+                coils.step(forward).ok().unwrap();
+                let log = coils.pins[0].log.borrow();
+                log[log.len() - 4..]
+                    .iter()
+                    .filter(|&&(_, high)| high)
+                    .fold(0, |pattern, &(id, _)| pattern | 1 << id)
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn half_steps_alternate_between_one_and_two_coils() {
+        let log = RefCell::new(Vec::new());
+        let pins = [pin(0, &log), pin(1, &log), pin(2, &log), pin(3, &log)];
+        let mut coils = UnipolarCoils::new(pins, StepMode::HalfStep);
+        assert_eq!(
+            patterns(&mut coils, &[true, true, true, false, false, false]),
+            [0b0011, 0b0010, 0b0110, 0b0010, 0b0011, 0b0001]
+        );
+        assert_eq!(patterns(&mut coils, &[false, false]), [0b1001, 0b1000]);
+    }
+
+    #[test]
+    pub fn direction_pin_only_changes_with_direction() {
+        const STEP: usize = 0;
+        const DIR: usize = 1;
+        const ENABLE: usize = 2;
+        let log = RefCell::new(Vec::new());
+        let mut driver =
+            StepDir::new(pin(STEP, &log), pin(DIR, &log)).with_enable(pin(ENABLE, &log));
+        for &forward in &[true, true, false] {
+            driver.step(forward).ok().unwrap();
+        }
+        driver.release().ok().unwrap();
+        assert_eq!(
+            *log.borrow(),
+            [
+                (ENABLE, false),
+                (DIR, true),
+                (STEP, true),
+                (STEP, false),
+                (STEP, true),
+                (STEP, false),
+                (DIR, false),
+                (STEP, true),
+                (STEP, false),
+                (ENABLE, true),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn overflowing_positions_are_rejected() {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            let log = RefCell::new(Vec::new());
+            let output = StepDir::new(pin(0, &log), pin(1, &log));
+            let mut stepper = Stepper::new(output, &timer_driver, Ramp::new(1000, 2000))?;
+
+            stepper.set_position(isize::MAX);
+            assert!(stepper.step(true).is_err());
+            assert_eq!(stepper.position(), isize::MAX);
+            stepper.step(false)?;
+            assert_eq!(stepper.position(), isize::MAX - 1);
+
+            stepper.set_position(isize::MIN);
+            {
+                let move_to = stepper.move_to(1);
+                ::futures::pin_mut!(move_to);
+                match executor::poll(move_to) {
+                    Poll::Ready(result) => assert!(result.is_err()),
+                    Poll::Pending => panic!("Moved although the distance overflows"),
+                }
+            }
+            {
+                let move_by = stepper.move_by(-1);
+                ::futures::pin_mut!(move_by);
+                match executor::poll(move_by) {
+                    Poll::Ready(result) => assert!(result.is_err()),
+                    Poll::Pending => panic!("Moved although the target overflows"),
+                }
+            }
+            assert_eq!(stepper.position(), isize::MIN);
+            assert_eq!(log.borrow().len(), 3);
+            Ok(())
+        });
+    }
+
+    #[test]
+    pub fn ramp_accelerates_and_decelerates_symmetrically() {
+        let ramp = Ramp::new(1000, 2000);
+        let intervals: Vec<_> = (0..10).map(|index| ramp.interval_us(index, 10)).collect();
+        assert_eq!(intervals[0], 1_000_000 / 63);
+        assert!(intervals[0] > intervals[1]);
+        assert_eq!(intervals[4], 1_000_000 / 141);
+        let mut reversed = intervals.clone();
+        reversed.reverse();
+        assert_eq!(intervals, reversed);
+
+        assert_eq!(ramp.interval_us(500, 1000), 1000);
+        assert_eq!(Ramp::new(500, 0).interval_us(0, 10), 2000);
+    }
+}
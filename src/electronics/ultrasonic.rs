@@ -0,0 +1,216 @@
+use crate::gpio::GpioRead;
+use crate::gpio::GpioState;
+use crate::gpio::GpioWrite;
+use crate::gpio::TriggerType;
+use crate::result::OtherError;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+use core::cell::Cell;
+
+/// Time the echo may take to end after the trigger pulse. Sensors without
+/// an obstacle in range answer with a pulse of about 38 ms.
+pub const DEFAULT_TIMEOUT: Duration<usize> = Duration::from_ms(30);
+
+/// Length of the trigger pulse
+const TRIGGER_PULSE_US: usize = 10;
+
+/// Speed of sound in dry air at 20 °C in mm/s
+const SPEED_OF_SOUND: usize = 343_000;
+
+/// Interval between checks whether the echo has ended
+const POLL_INTERVAL: Duration<usize> = Duration::from_ms(1);
+
+/// HC-SR04 ultrasonic distance sensor.
+///
+/// The width of the echo pulse is measured by timestamping its edges in the
+/// GPIO interrupt callback, which has to call
+/// [handle_event()](Self::handle_event). The accuracy depends on how quickly
+/// callbacks are delivered, so other work should be kept short while
+/// measuring. Wait at least 60 ms between measurements to avoid picking up
+/// echoes of the previous one.
+///
+/// Example usage:
+/// ```no_run
+/// # use libtock::electronics::Ultrasonic;
+/// # use libtock::gpio::ResistorMode;
+/// # use libtock::result::TockResult;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut trigger = gpios.next().unwrap();
+/// let mut echo = gpios.next().unwrap();
+/// let sensor = Ultrasonic::new(
+///     trigger.enable_output()?,
+///     echo.enable_input(ResistorMode::PullNone)?,
+///     &timer_driver,
+/// )?;
+///
+/// let mut callback = |gpio_num, state| sensor.handle_event(gpio_num, state);
+/// let _subscription = gpios.subscribe(&mut callback)?;
+/// let _distance_mm = sensor.measure().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Ultrasonic<'a> {
+    trigger: GpioWrite<'a>,
+    echo: GpioRead<'a>,
+    timer: &'a ParallelSleepDriver<'a>,
+    clock_frequency: usize,
+    timeout: Duration<usize>,
+    echo_start: Cell<Option<usize>>,
+    echo_end: Cell<Option<usize>>,
+}
+
+impl<'a> Ultrasonic<'a> {
+    /// Pulls the trigger pin low and enables interrupts on both edges of the
+    /// echo pin
+    pub fn new(
+        trigger: GpioWrite<'a>,
+        echo: GpioRead<'a>,
+        timer: &'a ParallelSleepDriver<'a>,
+    ) -> TockResult<Ultrasonic<'a>> {
+        trigger.set_low()?;
+        echo.enable_interrupt(TriggerType::EitherEdge)?;
+        let clock_frequency = timer.get_current_clock()?.clock_frequency().hz();
+        Ok(Ultrasonic {
+            trigger,
+            echo,
+            timer,
+            clock_frequency,
+            timeout: DEFAULT_TIMEOUT,
+            echo_start: Cell::new(None),
+            echo_end: Cell::new(None),
+        })
+    }
+
+    pub fn timeout(&self) -> Duration<usize> {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration<usize>) {
+        self.timeout = timeout;
+    }
+
+    /// Records the edges of the echo pulse. Events of other pins are ignored.
+    pub fn handle_event(&self, gpio_num: usize, state: GpioState) {
+        if gpio_num != self.echo.gpio_num() {
+            return;
+        }
+        let now = match self.timer.get_current_clock() {
+            Ok(clock) => clock.num_ticks() as usize,
+            Err(_) => return,
+        };
+        match state {
+            GpioState::High if self.echo_start.get().is_none() => self.echo_start.set(Some(now)),
+            GpioState::Low if self.echo_start.get().is_some() && self.echo_end.get().is_none() => {
+                self.echo_end.set(Some(now))
+            }
+            _ => {}
+        }
+    }
+
+    /// Discards the last echo and sends a trigger pulse
+    pub fn trigger(&self) -> TockResult<()> {
+        self.echo_start.set(None);
+        self.echo_end.set(None);
+        self.trigger.set_high()?;
+        let result = self
+            .timer
+            .busy_wait_ticks(timer::us_to_ticks(TRIGGER_PULSE_US, self.clock_frequency));
+        self.trigger.set_low()?;
+        result
+    }
+
+    /// Width of the last complete echo pulse in microseconds
+    pub fn echo_duration_us(&self) -> Option<usize> {
+        let start = self.echo_start.get()?;
+        let end = self.echo_end.get()?;
+        Some(ticks_to_us(end.wrapping_sub(start), self.clock_frequency))
+    }
+
+    /// Triggers a measurement and returns the distance in millimetres. Fails
+    /// with [OtherError::UltrasonicEchoTimeout] if the echo does not end
+    /// within the timeout.
+    pub async fn measure(&self) -> TockResult<usize> {
+        self.trigger()?;
+        let start = self.timer.get_current_clock()?.num_ticks() as usize;
+        let timeout_ticks = timer::ms_to_ticks(self.timeout.ms(), self.clock_frequency);
+        loop {
+            if let Some(echo_us) = self.echo_duration_us() {
+                return Ok(echo_to_mm(echo_us));
+            }
+            let now = self.timer.get_current_clock()?.num_ticks() as usize;
+            if now.wrapping_sub(start) > timeout_ticks {
+                return Err(OtherError::UltrasonicEchoTimeout.into());
+            }
+            self.timer.sleep(POLL_INTERVAL).await?;
+        }
+    }
+}
+
+fn ticks_to_us(ticks: usize, freq: usize) -> usize {
+    if freq == 0 {
+        return 0;
+    }
+    (ticks as u64 * 1_000_000 / freq as u64) as usize
+}
+
+/// Converts the width of the echo pulse into the distance to the obstacle.
+/// The sound travels there and back.
+fn echo_to_mm(echo_us: usize) -> usize {
+    (echo_us as u64 * SPEED_OF_SOUND as u64 / 2_000_000) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpio::ResistorMode;
+    use crate::syscalls;
+
+    #[test]
+    pub fn echo_width_is_converted_to_distance() {
+        assert_eq!(echo_to_mm(0), 0);
+        assert_eq!(echo_to_mm(5831), 1000);
+        assert_eq!(echo_to_mm(23_324), 4000);
+        assert_eq!(ticks_to_us(16_384, 32_768), 500_000);
+    }
+
+    #[test]
+    pub fn echo_edges_are_timestamped() {
+        let checked = Cell::new(false);
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            next_return.set(2);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            let mut gpios = gpio_driver.gpios();
+            let (mut trigger, mut echo) = (gpios.next().unwrap(), gpios.next().unwrap());
+            next_return.set(1_000_000);
+            let sensor = Ultrasonic::new(
+                trigger.enable_output()?,
+                echo.enable_input(ResistorMode::PullNone)?,
+                &timer_driver,
+            )?;
+
+            next_return.set(100);
+            sensor.handle_event(0, GpioState::High);
+            sensor.handle_event(1, GpioState::High);
+            assert_eq!(sensor.echo_duration_us(), None);
+            next_return.set(5931);
+            sensor.handle_event(1, GpioState::Low);
+            next_return.set(9000);
+            sensor.handle_event(1, GpioState::Low);
+            assert_eq!(sensor.echo_duration_us(), Some(5831));
+            checked.set(true);
+            Ok(())
+        });
+        assert!(checked.get());
+    }
+}
@@ -0,0 +1,92 @@
+use core::cell::Cell;
+
+/// Number of events an [EventQueue] can hold
+pub const CAPACITY: usize = 8;
+
+/// Fixed-capacity ring buffer for events received in callbacks. If the queue
+/// is full, the oldest event is dropped.
+///
+/// All operations take `&self`, so the queue can be filled from a callback
+/// while it is drained elsewhere.
+pub struct EventQueue<T: Copy> {
+    events: [Cell<Option<T>>; CAPACITY],
+    head: Cell<usize>,
+    len: Cell<usize>,
+    overflow_count: Cell<usize>,
+}
+
+impl<T: Copy> Default for EventQueue<T> {
+    fn default() -> Self {
+        EventQueue {
+            events: Default::default(),
+            head: Cell::new(0),
+            len: Cell::new(0),
+            overflow_count: Cell::new(0),
+        }
+    }
+}
+
+impl<T: Copy> EventQueue<T> {
+    /// Appends `event`, dropping the oldest event if the queue is full
+    pub fn push(&self, event: T) {
+        if self.len.get() == CAPACITY {
+            self.pop();
+            self.overflow_count.set(self.overflow_count.get() + 1);
+        }
+        self.events[(self.head.get() + self.len.get()) % CAPACITY].set(Some(event));
+        self.len.set(self.len.get() + 1);
+    }
+
+    /// Removes the oldest event
+    pub fn pop(&self) -> Option<T> {
+        if self.len.get() == 0 {
+            return None;
+        }
+        let event = self.events[self.head.get()].take();
+        self.head.set((self.head.get() + 1) % CAPACITY);
+        self.len.set(self.len.get() - 1);
+        event
+    }
+
+    /// Number of events currently waiting in the queue
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of events that were dropped because the queue was full
+    pub fn overflow_count(&self) -> usize {
+        self.overflow_count.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn events_are_popped_in_order() {
+        let queue = EventQueue::default();
+        queue.push(1);
+        queue.push(2);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    pub fn full_queue_drops_oldest_events() {
+        let queue = EventQueue::default();
+        for event in 0..CAPACITY + 2 {
+            queue.push(event);
+        }
+        assert_eq!(queue.overflow_count(), 2);
+        assert_eq!(queue.len(), CAPACITY);
+        assert_eq!(queue.pop(), Some(2));
+    }
+}
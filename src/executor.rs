@@ -26,7 +26,7 @@ pub unsafe fn block_on<T>(mut future: impl Future<Output = T>) -> T {
     }
 }
 
-fn poll<F: Future>(pinned_future: Pin<&mut F>) -> Poll<F::Output> {
+pub(crate) fn poll<F: Future>(pinned_future: Pin<&mut F>) -> Poll<F::Output> {
     let waker = unsafe { Waker::from_raw(get_dummy_waker()) };
     let mut context = Context::from_waker(&waker);
     pinned_future.poll(&mut context)
@@ -0,0 +1,313 @@
+//! Detection of click, double click and long press gestures on debounced
+//! inputs.
+
+use crate::debounce::Debounced;
+use crate::debounce::DigitalInput;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+use futures::stream;
+use futures::stream::Stream;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Gesture {
+    Click,
+    DoubleClick,
+    /// The input was held for at least the configured long press time. It is
+    /// reported while the input is still held, the payload is the time it had
+    /// been held by then. The release ends the gesture without a report.
+    LongPress(Duration<usize>),
+}
+
+/// Timing parameters of the gesture detection
+#[derive(Copy, Clone, Debug)]
+pub struct GestureConfig {
+    /// Time an input must be stable before a change is accepted
+    pub debounce: Duration<usize>,
+    /// Maximum time between releasing and pressing again for a double click.
+    /// A zero duration disables double click detection and reports clicks
+    /// immediately.
+    pub double_click: Duration<usize>,
+    /// Minimum time an input must be held to count as a long press
+    pub long_press: Duration<usize>,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            debounce: Duration::from_ms(20),
+            double_click: Duration::from_ms(300),
+            long_press: Duration::from_ms(800),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum RecognizerState {
+    Idle,
+    Pressed {
+        since: usize,
+        is_second: bool,
+    },
+    Released {
+        since: usize,
+    },
+    /// A long press has been reported, waiting for the release
+    LongPressed,
+}
+
+/// Gesture detection state machine operating on debounced states and clock
+/// ticks. Tick values are allowed to wrap around.
+#[derive(Copy, Clone, Debug)]
+pub struct GestureRecognizer {
+    clock_frequency: usize,
+    double_click_ticks: usize,
+    long_press_ticks: usize,
+    state: RecognizerState,
+    pending: Option<Gesture>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: &GestureConfig, clock_frequency: usize) -> GestureRecognizer {
+        GestureRecognizer {
+            clock_frequency,
+            double_click_ticks: timer::ms_to_ticks(config.double_click.ms(), clock_frequency),
+            long_press_ticks: timer::ms_to_ticks(config.long_press.ms(), clock_frequency),
+            state: RecognizerState::Idle,
+            pending: None,
+        }
+    }
+
+    /// Feeds the debounced state of the input at clock value `now`. Returns
+    /// a gesture once it has been recognized.
+    pub fn update(&mut self, active: bool, now: usize) -> Option<Gesture> {
+        if let Some(gesture) = self.pending.take() {
+            return Some(gesture);
+        }
+        match self.state {
+            RecognizerState::Idle => {
+                if active {
+                    self.state = RecognizerState::Pressed {
+                        since: now,
+                        is_second: false,
+                    };
+                }
+                None
+            }
+            RecognizerState::Pressed { since, is_second } => {
+                let held = now.wrapping_sub(since);
+                if active {
+                    if held < self.long_press_ticks {
+                        return None;
+                    }
+                    self.state = RecognizerState::LongPressed;
+                    return self.long_press(held, is_second);
+                }
+                self.state = RecognizerState::Idle;
+                // The input was not sampled between reaching the long press
+                // time and the release
+                if held >= self.long_press_ticks {
+                    self.long_press(held, is_second)
+                } else if is_second {
+                    Some(Gesture::DoubleClick)
+                } else if self.double_click_ticks == 0 {
+                    Some(Gesture::Click)
+                } else {
+                    self.state = RecognizerState::Released { since: now };
+                    None
+                }
+            }
+            RecognizerState::Released { since } => {
+                if active {
+                    self.state = RecognizerState::Pressed {
+                        since: now,
+                        is_second: true,
+                    };
+                    None
+                } else if now.wrapping_sub(since) > self.double_click_ticks {
+                    self.state = RecognizerState::Idle;
+                    Some(Gesture::Click)
+                } else {
+                    None
+                }
+            }
+            RecognizerState::LongPressed => {
+                if !active {
+                    self.state = RecognizerState::Idle;
+                }
+                None
+            }
+        }
+    }
+
+    /// Reports a long press after `held` ticks. A long press as the second
+    /// press of a double click is preceded by the click of the first press.
+    fn long_press(&mut self, held: usize, is_second: bool) -> Option<Gesture> {
+        let long_press = Gesture::LongPress(Duration::from_ms(timer::ticks_to_ms(
+            held,
+            self.clock_frequency,
+        )));
+        if is_second {
+            self.pending = Some(long_press);
+            Some(Gesture::Click)
+        } else {
+            Some(long_press)
+        }
+    }
+}
+
+/// Recognizes gestures on a [DigitalInput], e.g. a button.
+///
+/// Example usage:
+/// ```no_run
+/// # use futures::stream::StreamExt;
+/// # use libtock::gesture::GestureConfig;
+/// # use libtock::gesture::Gestures;
+/// # use libtock::result::TockResult;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let buttons_driver = drivers.buttons.init_driver()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+///
+/// let button = buttons_driver.get(0)?;
+/// let gestures = Gestures::new(button, &timer_driver, GestureConfig::default())?;
+/// let stream = gestures.into_stream();
+/// futures::pin_mut!(stream);
+/// while let Some(gesture) = stream.next().await {
+///     let _gesture = gesture?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Gestures<'a, I> {
+    input: Debounced<'a, I>,
+    timer: &'a ParallelSleepDriver<'a>,
+    recognizer: GestureRecognizer,
+    sample_interval: Duration<usize>,
+}
+
+impl<'a, I: DigitalInput> Gestures<'a, I> {
+    pub fn new(
+        input: I,
+        timer: &'a ParallelSleepDriver<'a>,
+        config: GestureConfig,
+    ) -> TockResult<Gestures<'a, I>> {
+        let clock_frequency = timer.get_current_clock()?.clock_frequency().hz();
+        Ok(Gestures {
+            input: Debounced::new(input, timer, config.debounce)?,
+            timer,
+            recognizer: GestureRecognizer::new(&config, clock_frequency),
+            sample_interval: Duration::from_ms((config.debounce.ms() / 4).max(1)),
+        })
+    }
+
+    /// Waits for the next gesture
+    pub async fn next_gesture(&mut self) -> TockResult<Gesture> {
+        loop {
+            self.input.poll()?;
+            let now = self.timer.get_current_clock()?.num_ticks() as usize;
+            if let Some(gesture) = self.recognizer.update(self.input.is_active(), now) {
+                return Ok(gesture);
+            }
+            self.timer.sleep(self.sample_interval).await?;
+        }
+    }
+
+    /// Turns this into an endless stream of gestures
+    pub fn into_stream(self) -> impl Stream<Item = TockResult<Gesture>> + 'a
+    where
+        I: 'a,
+    {
+        stream::unfold(self, |mut gestures| async move {
+            let gesture = gestures.next_gesture().await;
+            Some((gesture, gestures))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn recognizer() -> GestureRecognizer {
+        let config = GestureConfig {
+            debounce: Duration::from_ms(20),
+            double_click: Duration::from_ms(300),
+            long_press: Duration::from_ms(800),
+        };
+        GestureRecognizer::new(&config, 1000)
+    }
+
+    fn feed(recognizer: &mut GestureRecognizer, samples: &[(bool, usize)]) -> Vec<Gesture> {
+        samples
+            .iter()
+            .filter_map(|&(active, now)| recognizer.update(active, now))
+            .collect()
+    }
+
+    #[test]
+    pub fn click_is_reported_after_double_click_window() {
+        let mut recognizer = recognizer();
+        let samples = [(true, 0), (false, 100), (false, 400), (false, 401)];
+        assert_eq!(feed(&mut recognizer, &samples), vec![Gesture::Click]);
+    }
+
+    #[test]
+    pub fn double_click() {
+        let mut recognizer = recognizer();
+        let samples = [(true, 0), (false, 100), (true, 200), (false, 300)];
+        assert_eq!(feed(&mut recognizer, &samples), vec![Gesture::DoubleClick]);
+    }
+
+    #[test]
+    pub fn long_press_reports_held_time() {
+        let mut recognizer = recognizer();
+        let samples = [(true, 0), (false, 1200)];
+        assert_eq!(
+            feed(&mut recognizer, &samples),
+            vec![Gesture::LongPress(Duration::from_ms(1200))]
+        );
+    }
+
+    #[test]
+    pub fn long_press_is_reported_while_held() {
+        let mut recognizer = recognizer();
+        let samples = [(true, 0), (true, 799), (true, 850), (true, 5000)];
+        assert_eq!(
+            feed(&mut recognizer, &samples),
+            vec![Gesture::LongPress(Duration::from_ms(850))]
+        );
+        let samples = [(false, 5100), (false, 6000), (true, 6100), (false, 6200)];
+        assert_eq!(feed(&mut recognizer, &samples), vec![]);
+        assert_eq!(recognizer.update(false, 6600), Some(Gesture::Click));
+    }
+
+    #[test]
+    pub fn click_followed_by_long_press() {
+        let mut recognizer = recognizer();
+        let samples = [
+            (true, 0),
+            (false, 100),
+            (true, 200),
+            (false, 1100),
+            (false, 1101),
+        ];
+        assert_eq!(
+            feed(&mut recognizer, &samples),
+            vec![Gesture::Click, Gesture::LongPress(Duration::from_ms(900))]
+        );
+    }
+
+    #[test]
+    pub fn click_is_immediate_without_double_click_detection() {
+        let config = GestureConfig {
+            double_click: Duration::from_ms(0),
+            ..GestureConfig::default()
+        };
+        let mut recognizer = GestureRecognizer::new(&config, 1000);
+        let samples = [(true, 0), (false, 100)];
+        assert_eq!(feed(&mut recognizer, &samples), vec![Gesture::Click]);
+    }
+}
@@ -1,13 +1,17 @@
 use crate::callback::CallbackSubscription;
 use crate::callback::Consumer;
 use crate::result::OtherError;
+use crate::result::OutOfRangeError;
+use crate::result::TockError;
 use crate::result::TockResult;
 use crate::syscalls;
 use core::marker::PhantomData;
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::digital::v2::OutputPin;
 
-const DRIVER_NUMBER: usize = 0x00004;
+pub(crate) const DRIVER_NUMBER: usize = 0x00004;
 
-mod command_nr {
+pub(crate) mod command_nr {
     pub const COUNT: usize = 0;
     pub const ENABLE_OUTPUT: usize = 1;
     pub const SET_HIGH: usize = 2;
@@ -112,6 +116,23 @@ pub struct Gpios<'a> {
     lifetime: PhantomData<&'a ()>,
 }
 
+impl<'a> Gpios<'a> {
+    /// Same as [GpioDriver::subscribe()], but can be used while pins handed
+    /// out by this iterator are in use, e.g. to feed their interrupts into a
+    /// structure owning the pins.
+    pub fn subscribe<CB: Fn(usize, GpioState)>(
+        &self,
+        callback: &'a mut CB,
+    ) -> TockResult<CallbackSubscription<'a>> {
+        syscalls::subscribe::<GpioEventConsumer, _>(
+            DRIVER_NUMBER,
+            subscribe_nr::SUBSCRIBE_CALLBACK,
+            callback,
+        )
+        .map_err(Into::into)
+    }
+}
+
 impl<'a> Iterator for Gpios<'a> {
     type Item = Gpio<'a>;
 
@@ -157,6 +178,28 @@ impl<'a> Gpio<'a> {
         };
         Ok(gpio_read)
     }
+
+    /// Emulates an open-drain output: the pin is either driven low or
+    /// released to an input with pull-up.
+    pub fn enable_open_drain(&mut self) -> TockResult<GpioOpenDrain> {
+        let gpio_open_drain = GpioOpenDrain {
+            gpio_num: self.gpio_num,
+            lifetime: PhantomData,
+        };
+        gpio_open_drain.release()?;
+        Ok(gpio_open_drain)
+    }
+
+    /// Makes the pin switchable between driving high, driving low and
+    /// floating, e.g. for charlieplexing. The pin starts floating.
+    pub fn enable_tri_state(&mut self) -> TockResult<GpioTriState> {
+        let gpio_tri_state = GpioTriState {
+            gpio_num: self.gpio_num,
+            lifetime: PhantomData,
+        };
+        gpio_tri_state.float()?;
+        Ok(gpio_tri_state)
+    }
 }
 
 pub struct GpioWrite<'a> {
@@ -198,6 +241,30 @@ impl<'a> Drop for GpioWrite<'a> {
     }
 }
 
+impl<'a> OutputPin for GpioWrite<'a> {
+    type Error = TockError;
+
+    fn set_low(&mut self) -> TockResult<()> {
+        GpioWrite::set_low(self)
+    }
+
+    fn set_high(&mut self) -> TockResult<()> {
+        GpioWrite::set_high(self)
+    }
+}
+
+impl<'a, 'b> OutputPin for &'b GpioWrite<'a> {
+    type Error = TockError;
+
+    fn set_low(&mut self) -> TockResult<()> {
+        GpioWrite::set_low(self)
+    }
+
+    fn set_high(&mut self) -> TockResult<()> {
+        GpioWrite::set_high(self)
+    }
+}
+
 pub struct GpioRead<'a> {
     gpio_num: usize,
     lifetime: PhantomData<&'a ()>,
@@ -244,6 +311,108 @@ impl<'a> Drop for GpioRead<'a> {
     }
 }
 
+impl<'a> InputPin for GpioRead<'a> {
+    type Error = TockError;
+
+    fn is_high(&self) -> TockResult<bool> {
+        Ok(self.read()? == GpioState::High)
+    }
+
+    fn is_low(&self) -> TockResult<bool> {
+        Ok(self.read()? == GpioState::Low)
+    }
+}
+
+pub struct GpioOpenDrain<'a> {
+    gpio_num: usize,
+    lifetime: PhantomData<&'a ()>,
+}
+
+impl<'a> GpioOpenDrain<'a> {
+    pub fn gpio_num(&self) -> usize {
+        self.gpio_num
+    }
+
+    /// Drives the line low. The output level is cleared before switching to
+    /// output mode so the line is never actively driven high.
+    pub fn set_low(&self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_LOW, self.gpio_num, 0)?;
+        syscalls::command(DRIVER_NUMBER, command_nr::ENABLE_OUTPUT, self.gpio_num, 0)?;
+        Ok(())
+    }
+
+    /// Stops driving the line and lets the pull-up resistor pull it high
+    pub fn release(&self) -> TockResult<()> {
+        syscalls::command(
+            DRIVER_NUMBER,
+            command_nr::ENABLE_INPUT,
+            self.gpio_num,
+            ResistorMode::PullUp as usize,
+        )?;
+        Ok(())
+    }
+
+    /// Reads the level of the line. Only meaningful while it is released.
+    pub fn read(&self) -> TockResult<GpioState> {
+        let state = syscalls::command(DRIVER_NUMBER, command_nr::READ, self.gpio_num, 0)?;
+        match state {
+            0 => Ok(GpioState::Low),
+            1 => Ok(GpioState::High),
+            _ => Err(OtherError::GpioDriverInvalidState.into()),
+        }
+    }
+}
+
+impl<'a> Drop for GpioOpenDrain<'a> {
+    fn drop(&mut self) {
+        let _ = syscalls::command(DRIVER_NUMBER, command_nr::DISABLE, self.gpio_num, 0);
+    }
+}
+
+pub struct GpioTriState<'a> {
+    gpio_num: usize,
+    lifetime: PhantomData<&'a ()>,
+}
+
+impl<'a> GpioTriState<'a> {
+    pub fn gpio_num(&self) -> usize {
+        self.gpio_num
+    }
+
+    /// Drives the pin high. The output level is set before switching to
+    /// output mode to avoid glitches.
+    pub fn drive_high(&self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_HIGH, self.gpio_num, 0)?;
+        syscalls::command(DRIVER_NUMBER, command_nr::ENABLE_OUTPUT, self.gpio_num, 0)?;
+        Ok(())
+    }
+
+    /// Drives the pin low. The output level is set before switching to
+    /// output mode to avoid glitches.
+    pub fn drive_low(&self) -> TockResult<()> {
+        syscalls::command(DRIVER_NUMBER, command_nr::SET_LOW, self.gpio_num, 0)?;
+        syscalls::command(DRIVER_NUMBER, command_nr::ENABLE_OUTPUT, self.gpio_num, 0)?;
+        Ok(())
+    }
+
+    /// Switches the pin to a high impedance input without pull resistor
+    pub fn float(&self) -> TockResult<()> {
+        syscalls::command(
+            DRIVER_NUMBER,
+            command_nr::ENABLE_INPUT,
+            self.gpio_num,
+            ResistorMode::PullNone as usize,
+        )?;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for GpioTriState<'a> {
+    fn drop(&mut self) {
+        let _ = syscalls::command(DRIVER_NUMBER, command_nr::DISABLE, self.gpio_num, 0);
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ResistorMode {
     PullNone = 0,
@@ -257,3 +426,194 @@ pub enum TriggerType {
     RisingEdge = 1,
     FallingEdge = 2,
 }
+
+/// Order in which the bits of a value are mapped to a sequence of pins or
+/// transferred over a serial line.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BitOrder {
+    /// The first pin carries the least significant bit
+    LsbFirst,
+    /// The first pin carries the most significant bit
+    MsbFirst,
+}
+
+/// A group of up to 32 pins that are written or read as a single value, e.g.
+/// the data lines of a parallel bus.
+///
+/// Example usage (4-bit output bus):
+/// ```no_run
+/// # use libtock::gpio::BitOrder;
+/// # use libtock::gpio::Bus;
+/// # use libtock::result::TockResult;
+/// # fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let mut gpio_driver = drivers.gpio.init_driver()?;
+/// let mut gpios = gpio_driver.gpios();
+/// let mut gpio0 = gpios.next().unwrap();
+/// let mut gpio1 = gpios.next().unwrap();
+/// let mut gpio2 = gpios.next().unwrap();
+/// let mut gpio3 = gpios.next().unwrap();
+/// let pins = [
+///     gpio0.enable_output()?,
+///     gpio1.enable_output()?,
+///     gpio2.enable_output()?,
+///     gpio3.enable_output()?,
+/// ];
+/// let mut bus = Bus::new(pins, BitOrder::LsbFirst)?;
+/// bus.write(0b1010)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Bus<P, const N: usize> {
+    pins: [P; N],
+    bit_order: BitOrder,
+    last_written: Option<u32>,
+}
+
+impl<P, const N: usize> Bus<P, N> {
+    pub fn new(pins: [P; N], bit_order: BitOrder) -> Result<Bus<P, N>, OutOfRangeError> {
+        if N > 32 {
+            return Err(OutOfRangeError);
+        }
+        Ok(Bus {
+            pins,
+            bit_order,
+            last_written: None,
+        })
+    }
+
+    pub fn pins(&self) -> &[P] {
+        &self.pins
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    pub fn into_pins(self) -> [P; N] {
+        self.pins
+    }
+
+    fn bit_of_pin(&self, pin_index: usize) -> usize {
+        match self.bit_order {
+            BitOrder::LsbFirst => pin_index,
+            BitOrder::MsbFirst => N - 1 - pin_index,
+        }
+    }
+}
+
+impl<'a, const N: usize> Bus<GpioWrite<'a>, N> {
+    /// Writes the lowest `N` bits of `value` to the bus. Only pins whose
+    /// state differs from the last written value are changed.
+    pub fn write(&mut self, value: u32) -> TockResult<()> {
+        for (pin_index, pin) in self.pins.iter().enumerate() {
+            let bit = 1 << self.bit_of_pin(pin_index);
+            let unchanged = self
+                .last_written
+                .map_or(false, |last_written| (last_written ^ value) & bit == 0);
+            if unchanged {
+                continue;
+            }
+            if value & bit != 0 {
+                pin.set_high()?;
+            } else {
+                pin.set_low()?;
+            }
+        }
+        self.last_written = Some(value);
+        Ok(())
+    }
+
+    /// The value last written to the bus, if any
+    pub fn last_written(&self) -> Option<u32> {
+        self.last_written
+    }
+}
+
+impl<'a, const N: usize> Bus<GpioRead<'a>, N> {
+    /// Reads the state of all pins into the lowest `N` bits of the result.
+    pub fn read(&self) -> TockResult<u32> {
+        let mut value = 0;
+        for (pin_index, pin) in self.pins.iter().enumerate() {
+            if pin.read()? == GpioState::High {
+                value |= 1 << self.bit_of_pin(pin_index);
+            }
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syscalls::raw::Event;
+
+    #[test]
+    pub fn bus_only_writes_changed_bits() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(3);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            next_return.set(0);
+            let mut gpios = gpio_driver.gpios();
+            let (mut gpio0, mut gpio1, mut gpio2) = (
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+            );
+            let pins = [
+                gpio0.enable_output()?,
+                gpio1.enable_output()?,
+                gpio2.enable_output()?,
+            ];
+            let mut bus = Bus::new(pins, BitOrder::MsbFirst).ok().unwrap();
+            bus.write(0b001)?;
+            bus.write(0b011)?;
+            core::mem::forget(bus);
+            Ok(())
+        });
+        let writes: Vec<_> = events
+            .into_iter()
+            .filter(|event| match event {
+                Event::Command(DRIVER_NUMBER, command_nr::SET_HIGH, _, _)
+                | Event::Command(DRIVER_NUMBER, command_nr::SET_LOW, _, _) => true,
+                _ => false,
+            })
+            .collect();
+        assert_eq!(
+            writes,
+            vec![
+                Event::Command(DRIVER_NUMBER, command_nr::SET_LOW, 0, 0),
+                Event::Command(DRIVER_NUMBER, command_nr::SET_LOW, 1, 0),
+                Event::Command(DRIVER_NUMBER, command_nr::SET_HIGH, 2, 0),
+                Event::Command(DRIVER_NUMBER, command_nr::SET_HIGH, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn bus_rejects_more_than_32_pins() {
+        assert!(Bus::new([(); 32], BitOrder::LsbFirst).is_ok());
+        assert!(Bus::new([(); 33], BitOrder::LsbFirst).is_err());
+    }
+
+    #[test]
+    pub fn bus_reads_pins_in_bit_order() {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(2);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            next_return.set(0);
+            let mut gpios = gpio_driver.gpios();
+            let (mut gpio0, mut gpio1) = (gpios.next().unwrap(), gpios.next().unwrap());
+            let pins = [
+                gpio0.enable_input(ResistorMode::PullNone)?,
+                gpio1.enable_input(ResistorMode::PullNone)?,
+            ];
+            let bus = Bus::new(pins, BitOrder::LsbFirst).ok().unwrap();
+            next_return.set(1);
+            assert_eq!(bus.read()?, 0b11);
+            Ok(())
+        });
+    }
+}
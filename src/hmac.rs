@@ -1,7 +1,21 @@
 use crate::callback::CallbackSubscription;
 use crate::callback::Consumer;
+use crate::crypto;
+#[cfg(feature = "software_hmac")]
+use crate::result::CommandError;
+use crate::result::OtherError;
+#[cfg(feature = "software_hmac")]
+use crate::result::TockError;
 use crate::result::TockResult;
+#[cfg(feature = "software_hmac")]
+use crate::result::ENOSUPPORT;
+use crate::result::SUCCESS;
+#[cfg(feature = "software_hmac")]
+use crate::sha256;
+#[cfg(feature = "software_hmac")]
+use crate::sha256::HmacSha256;
 use crate::syscalls;
+use core::fmt;
 use core::marker::PhantomData;
 use libtock_core::shared_memory::SharedMemory;
 
@@ -10,10 +24,13 @@ const DRIVER_NUMBER: usize = 0x40003;
 pub const KEY_BUFFER_SIZE: usize = 32;
 pub const DATA_BUFFER_SIZE: usize = 256;
 pub const DEST_BUFFER_SIZE: usize = 32;
+pub const MAX_DIGEST_SIZE: usize = 64;
 
 mod command_nr {
     pub const SET_ALGORITHM: usize = 0;
     pub const RUN: usize = 1;
+    pub const UPDATE: usize = 2;
+    pub const FINISH: usize = 3;
 }
 
 mod subscribe_nr {
@@ -26,6 +43,91 @@ mod allow_nr {
     pub const DEST: usize = 2;
 }
 
+/// Hash function underlying the HMAC
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HmacAlgorithm {
+    Sha256 = 0,
+    Sha384 = 1,
+    Sha512 = 2,
+}
+
+impl HmacAlgorithm {
+    /// Length of the digest in bytes
+    pub fn digest_len(self) -> usize {
+        match self {
+            HmacAlgorithm::Sha256 => 32,
+            HmacAlgorithm::Sha384 => 48,
+            HmacAlgorithm::Sha512 => 64,
+        }
+    }
+}
+
+/// Result of [HmacDriver::compute()]. Check a received tag with
+/// [verify()](Self::verify) rather than `==`, which is not constant-time.
+#[derive(Copy, Clone)]
+pub struct Digest {
+    algorithm: HmacAlgorithm,
+    bytes: [u8; MAX_DIGEST_SIZE],
+}
+
+impl Digest {
+    fn new(algorithm: HmacAlgorithm) -> Digest {
+        Digest {
+            algorithm,
+            bytes: [0; MAX_DIGEST_SIZE],
+        }
+    }
+
+    pub fn algorithm(&self) -> HmacAlgorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.algorithm.digest_len()]
+    }
+
+    /// Compares the digest with `tag` in constant time
+    pub fn verify(&self, tag: &[u8]) -> bool {
+        let bytes = self.as_bytes();
+        if tag.len() != bytes.len() {
+            return false;
+        }
+        let difference = bytes
+            .iter()
+            .zip(tag)
+            .fold(0, |difference, (byte, tag_byte)| {
+                difference | (byte ^ tag_byte)
+            });
+        difference == 0
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.algorithm.digest_len()]
+    }
+}
+
+impl AsRef<[u8]> for Digest {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl PartialEq for Digest {
+    fn eq(&self, other: &Digest) -> bool {
+        self.algorithm == other.algorithm && self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for Digest {}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}(", self.algorithm)?;
+        crypto::fmt_hex(self.as_bytes(), f)?;
+        write!(f, ")")
+    }
+}
+
 #[non_exhaustive]
 pub struct HmacDriverFactory;
 
@@ -58,6 +160,13 @@ impl Default for HmacKeyBuffer {
     }
 }
 
+/// The key is overwritten with zeros when the buffer is dropped
+impl Drop for HmacKeyBuffer {
+    fn drop(&mut self) {
+        crypto::ZeroOnDrop(&mut self.buffer);
+    }
+}
+
 pub struct HmacDataBuffer {
     pub buffer: [u8; DATA_BUFFER_SIZE],
 }
@@ -120,4 +229,499 @@ impl<'a> HmacDriver<'a> {
         syscalls::command(DRIVER_NUMBER, command_nr::RUN, 0, 0)?;
         Ok(())
     }
+
+    /// Computes the HMAC of `data` with `key`. Both are copied into buffers
+    /// shared with the kernel, so `key` may be at most [KEY_BUFFER_SIZE] and
+    /// `data` at most [DATA_BUFFER_SIZE] bytes long. Replaces the callback
+    /// registered with [subscribe()](Self::subscribe) while running.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use libtock::hmac::HmacAlgorithm;
+    /// # use libtock::result::TockResult;
+    /// # async fn doc(received_tag: &[u8]) -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let hmac_driver = drivers.hmac.init_driver()?;
+    /// let digest = hmac_driver
+    ///     .compute(HmacAlgorithm::Sha256, b"key", b"message")
+    ///     .await?;
+    /// let _authentic = digest.verify(received_tag);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn compute(
+        &self,
+        algorithm: HmacAlgorithm,
+        key: &[u8],
+        data: &[u8],
+    ) -> TockResult<Digest> {
+        if key.len() > KEY_BUFFER_SIZE {
+            return Err(OtherError::HmacKeyTooLong.into());
+        }
+        if data.len() > DATA_BUFFER_SIZE {
+            return Err(OtherError::HmacDataTooLong.into());
+        }
+        let mut key_buffer = [0; KEY_BUFFER_SIZE];
+        let mut key_buffer = crypto::ZeroOnDrop(&mut key_buffer[..key.len()]);
+        key_buffer.copy_from_slice(key);
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+        let mut data_buffer = crypto::ZeroOnDrop(&mut data_buffer[..data.len()]);
+        data_buffer.copy_from_slice(data);
+        let mut digest = Digest::new(algorithm);
+        self.execute(
+            command_nr::RUN,
+            Some((algorithm, &mut key_buffer)),
+            Some(&mut data_buffer),
+            Some(digest.bytes_mut()),
+        )
+        .await?;
+        Ok(digest)
+    }
+
+    /// Starts an incremental HMAC computation over messages of any length.
+    /// `key` may be at most [KEY_BUFFER_SIZE] bytes long.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use libtock::hmac::HmacAlgorithm;
+    /// # use libtock::result::TockResult;
+    /// # async fn doc(image: &[u8], log: &[u8]) -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let hmac_driver = drivers.hmac.init_driver()?;
+    /// let mut context = hmac_driver.context(HmacAlgorithm::Sha256, b"key")?;
+    /// context.update(image).await?;
+    /// context.update(log).await?;
+    /// let _digest = context.finalize().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn context(&self, algorithm: HmacAlgorithm, key: &[u8]) -> TockResult<HmacContext> {
+        if key.len() > KEY_BUFFER_SIZE {
+            return Err(OtherError::HmacKeyTooLong.into());
+        }
+        let mut key_buffer = HmacKeyBuffer::default();
+        key_buffer.buffer[..key.len()].copy_from_slice(key);
+        Ok(HmacContext {
+            algorithm,
+            key: key_buffer,
+            key_len: key.len(),
+            backend: Backend::Undetermined(self),
+        })
+    }
+
+    /// Shares the buffers with the kernel, runs `command` and waits for its
+    /// completion. The algorithm and key in `setup` only need to be passed
+    /// with the first command of a computation.
+    async fn execute(
+        &self,
+        command: usize,
+        setup: Option<(HmacAlgorithm, &mut [u8])>,
+        data: Option<&mut [u8]>,
+        dest: Option<&mut [u8]>,
+    ) -> TockResult<()> {
+        let mut commands = [(command_nr::SET_ALGORITHM, 0, 0), (command, 0, 0)];
+        let (key, commands) = match setup {
+            Some((algorithm, key)) => {
+                commands[0].1 = algorithm as usize;
+                (Some(key), &commands[..])
+            }
+            None => (None, &commands[1..]),
+        };
+        let (result_code, _) = crypto::run(
+            DRIVER_NUMBER,
+            &mut [
+                (allow_nr::KEY, key),
+                (allow_nr::DATA, data),
+                (allow_nr::DEST, dest),
+            ],
+            commands,
+        )
+        .await?;
+        match result_code {
+            SUCCESS => Ok(()),
+            _ => Err(OtherError::HmacFailed.into()),
+        }
+    }
+}
+
+impl<'a> Hmac for HmacDriver<'a> {
+    fn context(&self, algorithm: HmacAlgorithm, key: &[u8]) -> TockResult<HmacContext> {
+        HmacDriver::context(self, algorithm, key)
+    }
+}
+
+/// Common interface of [HmacDriver] and `SoftwareHmac` (feature `software_hmac`),
+/// allowing code to be agnostic of whether the board provides the HMAC capsule
+pub trait Hmac {
+    /// Starts an incremental HMAC computation
+    fn context(&self, algorithm: HmacAlgorithm, key: &[u8]) -> TockResult<HmacContext>;
+}
+
+/// Computes the HMAC of `data` with `key` using any [Hmac] implementation
+///
+/// Example usage:
+/// ```no_run
+/// # use libtock::hmac;
+/// # use libtock::hmac::{Hmac, HmacAlgorithm};
+/// # use libtock::result::TockResult;
+/// async fn sign<H: Hmac>(hmac: &H, message: &[u8]) -> TockResult<hmac::Digest> {
+///     hmac::compute(hmac, HmacAlgorithm::Sha256, b"key", message).await
+/// }
+/// ```
+pub async fn compute<H: Hmac>(
+    hmac: &H,
+    algorithm: HmacAlgorithm,
+    key: &[u8],
+    data: &[u8],
+) -> TockResult<Digest> {
+    let mut context = hmac.context(algorithm, key)?;
+    context.update(data).await?;
+    context.finalize().await
+}
+
+/// Software implementation of HMAC-SHA256 for boards without the HMAC
+/// capsule. Other algorithms fail with
+/// [OtherError::HmacAlgorithmNotSupported]. Keys may have any length.
+#[cfg(feature = "software_hmac")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SoftwareHmac;
+
+#[cfg(feature = "software_hmac")]
+impl Hmac for SoftwareHmac {
+    fn context(&self, algorithm: HmacAlgorithm, key: &[u8]) -> TockResult<HmacContext> {
+        Ok(HmacContext {
+            algorithm,
+            key: HmacKeyBuffer::default(),
+            key_len: 0,
+            backend: Backend::software(algorithm, key)?,
+        })
+    }
+}
+
+enum Backend<'a> {
+    /// No data has been passed to the kernel yet
+    Undetermined(&'a HmacDriver<'a>),
+    Kernel(&'a HmacDriver<'a>),
+    #[cfg(feature = "software_hmac")]
+    Software(HmacSha256),
+}
+
+#[cfg(feature = "software_hmac")]
+impl<'a> Backend<'a> {
+    fn software(algorithm: HmacAlgorithm, key: &[u8]) -> TockResult<Backend<'a>> {
+        if algorithm != HmacAlgorithm::Sha256 {
+            return Err(OtherError::HmacAlgorithmNotSupported.into());
+        }
+        Ok(Backend::Software(HmacSha256::new(key)))
+    }
+}
+
+/// Incremental HMAC computation created by [Hmac::context()].
+///
+/// The data is passed to the kernel in chunks of [DATA_BUFFER_SIZE] bytes.
+/// If the kernel does not support incremental operation and the
+/// `software_hmac` feature is enabled, HMAC-SHA256 falls back to a software
+/// implementation and other algorithms fail with
+/// [OtherError::HmacAlgorithmNotSupported]. Without the feature, the kernel
+/// error is returned. The copy of the key is overwritten with zeros when the
+/// context is dropped.
+pub struct HmacContext<'a> {
+    algorithm: HmacAlgorithm,
+    key: HmacKeyBuffer,
+    key_len: usize,
+    backend: Backend<'a>,
+}
+
+impl<'a> HmacContext<'a> {
+    pub fn algorithm(&self) -> HmacAlgorithm {
+        self.algorithm
+    }
+
+    /// Whether the software implementation is used
+    pub fn is_software(&self) -> bool {
+        self.kernel_driver().is_none()
+    }
+
+    fn kernel_driver(&self) -> Option<&'a HmacDriver<'a>> {
+        match self.backend {
+            Backend::Undetermined(driver) | Backend::Kernel(driver) => Some(driver),
+            #[cfg(feature = "software_hmac")]
+            Backend::Software(_) => None,
+        }
+    }
+
+    /// The algorithm and key to pass with the next kernel command. The kernel
+    /// keeps them for the rest of the computation once a command succeeded.
+    fn kernel_setup(&mut self) -> Option<(HmacAlgorithm, &mut [u8])> {
+        match self.backend {
+            Backend::Undetermined(_) => {
+                Some((self.algorithm, &mut self.key.buffer[..self.key_len]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Appends `data` to the message
+    pub async fn update(&mut self, data: &[u8]) -> TockResult<()> {
+        for chunk in data.chunks(DATA_BUFFER_SIZE) {
+            if let Some(driver) = self.kernel_driver() {
+                let mut data_buffer = [0; DATA_BUFFER_SIZE];
+                let data_buffer = &mut data_buffer[..chunk.len()];
+                data_buffer.copy_from_slice(chunk);
+                let result = driver
+                    .execute(
+                        command_nr::UPDATE,
+                        self.kernel_setup(),
+                        Some(data_buffer),
+                        None,
+                    )
+                    .await;
+                self.check_kernel_result(driver, result)?;
+            }
+            #[cfg(feature = "software_hmac")]
+            {
+                if let Backend::Software(hmac) = &mut self.backend {
+                    hmac.update(chunk);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the computation and returns the digest
+    pub async fn finalize(mut self) -> TockResult<Digest> {
+        let mut digest = Digest::new(self.algorithm);
+        if let Some(driver) = self.kernel_driver() {
+            let result = driver
+                .execute(
+                    command_nr::FINISH,
+                    self.kernel_setup(),
+                    None,
+                    Some(digest.bytes_mut()),
+                )
+                .await;
+            self.check_kernel_result(driver, result)?;
+        }
+        #[cfg(feature = "software_hmac")]
+        {
+            if let Backend::Software(hmac) = self.backend {
+                digest.bytes[..sha256::DIGEST_SIZE].copy_from_slice(&hmac.finalize());
+            }
+        }
+        Ok(digest)
+    }
+
+    /// Switches to the software implementation (feature `software_hmac`) if
+    /// the kernel rejects the first incremental command as not supported
+    fn check_kernel_result(
+        &mut self,
+        driver: &'a HmacDriver<'a>,
+        result: TockResult<()>,
+    ) -> TockResult<()> {
+        match (result, &self.backend) {
+            (Ok(()), _) => {
+                self.backend = Backend::Kernel(driver);
+                Ok(())
+            }
+            #[cfg(feature = "software_hmac")]
+            (
+                Err(TockError::Command(CommandError {
+                    return_code: ENOSUPPORT,
+                    ..
+                })),
+                Backend::Undetermined(_),
+            ) => {
+                self.backend = Backend::software(self.algorithm, &self.key.buffer[..self.key_len])?;
+                Ok(())
+            }
+            (Err(error), _) => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor;
+    use crate::result::CommandError;
+    use crate::result::TockError;
+    use crate::result::ENOSUPPORT;
+    use crate::syscalls::raw::Event;
+    use core::task::Poll;
+
+    #[test]
+    pub fn digests_are_verified_with_length() {
+        let mut digest = Digest::new(HmacAlgorithm::Sha256);
+        digest.bytes_mut()[0] = 1;
+        let mut tag = [0; 32];
+        tag[0] = 1;
+        assert!(digest.verify(&tag));
+        assert!(!digest.verify(&tag[..31]));
+        assert!(!digest.verify(&[0; 32]));
+    }
+
+    #[test]
+    pub fn compute_validates_lengths_and_shares_buffers() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let hmac_driver = drivers.hmac.init_driver()?;
+
+            let long_key = [0; KEY_BUFFER_SIZE + 1];
+            let too_long = hmac_driver.compute(HmacAlgorithm::Sha256, &long_key, b"data");
+            ::futures::pin_mut!(too_long);
+            match executor::poll(too_long) {
+                Poll::Ready(Err(TockError::Other(OtherError::HmacKeyTooLong))) => {}
+                _ => panic!("Key should be rejected"),
+            }
+
+            let compute = hmac_driver.compute(HmacAlgorithm::Sha384, b"key", b"message");
+            ::futures::pin_mut!(compute);
+            assert!(executor::poll(compute).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            crypto::summarize(DRIVER_NUMBER, events)[..6],
+            [
+                (0, allow_nr::KEY, 3, 0),
+                (0, allow_nr::DATA, 7, 0),
+                (0, allow_nr::DEST, 48, 0),
+                (1, subscribe_nr::SUBSCRIBE_CALLBACK, 0, 0),
+                (
+                    2,
+                    command_nr::SET_ALGORITHM,
+                    HmacAlgorithm::Sha384 as usize,
+                    0
+                ),
+                (2, command_nr::RUN, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn context_feeds_kernel_in_chunks() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let hmac_driver = drivers.hmac.init_driver()?;
+            let mut context = hmac_driver.context(HmacAlgorithm::Sha256, b"key")?;
+            let message = [0; DATA_BUFFER_SIZE + 44];
+            {
+                let update = context.update(&message);
+                ::futures::pin_mut!(update);
+                assert!(executor::poll(update).is_pending());
+            }
+            assert!(!context.is_software());
+            Ok(())
+        });
+        match events[..] {
+            [Event::Allow(DRIVER_NUMBER, allow_nr::KEY, _, 3), Event::Allow(DRIVER_NUMBER, allow_nr::DATA, _, DATA_BUFFER_SIZE), Event::Subscribe(DRIVER_NUMBER, subscribe_nr::SUBSCRIBE_CALLBACK, _, _), Event::Command(DRIVER_NUMBER, command_nr::SET_ALGORITHM, 0, 0), Event::Command(DRIVER_NUMBER, command_nr::UPDATE, 0, 0), ..] =>
+                {}
+            _ => panic!("Unexpected events: {:?}", events),
+        }
+    }
+
+    #[test]
+    pub fn configured_context_only_passes_data() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let hmac_driver = drivers.hmac.init_driver()?;
+            let mut context = hmac_driver.context(HmacAlgorithm::Sha256, b"key")?;
+            context.backend = Backend::Kernel(&hmac_driver);
+            let update = context.update(b"message");
+            ::futures::pin_mut!(update);
+            assert!(executor::poll(update).is_pending());
+            Ok(())
+        });
+        match events[..] {
+            [Event::Allow(DRIVER_NUMBER, allow_nr::DATA, _, 7), Event::Subscribe(DRIVER_NUMBER, subscribe_nr::SUBSCRIBE_CALLBACK, _, _), Event::Command(DRIVER_NUMBER, command_nr::UPDATE, 0, 0), ..] =>
+                {}
+            _ => panic!("Unexpected events: {:?}", events),
+        }
+    }
+
+    fn unsupported() -> TockError {
+        TockError::Command(CommandError {
+            driver_number: DRIVER_NUMBER,
+            command_number: command_nr::UPDATE,
+            arg1: 0,
+            arg2: 0,
+            return_code: ENOSUPPORT,
+        })
+    }
+
+    #[cfg(not(feature = "software_hmac"))]
+    #[test]
+    pub fn unsupported_kernel_is_reported() {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let hmac_driver = drivers.hmac.init_driver()?;
+            let mut context = hmac_driver.context(HmacAlgorithm::Sha256, b"Jefe")?;
+            assert!(context
+                .check_kernel_result(&hmac_driver, Err(unsupported()))
+                .is_err());
+            assert!(!context.is_software());
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "software_hmac")]
+    #[test]
+    pub fn unsupported_kernel_falls_back_to_software() {
+        syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let hmac_driver = drivers.hmac.init_driver()?;
+            let mut context = hmac_driver.context(HmacAlgorithm::Sha256, b"Jefe")?;
+            context.check_kernel_result(&hmac_driver, Err(unsupported()))?;
+            assert!(context.is_software());
+
+            {
+                let update = context.update(b"what do ya want for nothing?");
+                ::futures::pin_mut!(update);
+                match executor::poll(update) {
+                    Poll::Ready(result) => result?,
+                    Poll::Pending => panic!("Software HMAC should complete immediately"),
+                }
+            }
+            let digest = {
+                let finalize = context.finalize();
+                ::futures::pin_mut!(finalize);
+                match executor::poll(finalize) {
+                    Poll::Ready(result) => result?,
+                    Poll::Pending => panic!("Software HMAC should complete immediately"),
+                }
+            };
+            // RFC 4231, test case 2
+            assert_eq!(
+                digest.as_bytes(),
+                &[
+                    0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08,
+                    0x95, 0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec,
+                    0x58, 0xb9, 0x64, 0xec, 0x38, 0x43,
+                ][..]
+            );
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "software_hmac")]
+    #[test]
+    pub fn software_hmac_implements_trait() {
+        let compute = compute(
+            &SoftwareHmac,
+            HmacAlgorithm::Sha256,
+            b"Jefe",
+            b"what do ya want for nothing?",
+        );
+        ::futures::pin_mut!(compute);
+        let digest = match executor::poll(compute) {
+            Poll::Ready(Ok(digest)) => digest,
+            _ => panic!("Software HMAC should complete immediately"),
+        };
+        assert_eq!(&digest.as_bytes()[..4], [0x5b, 0xdc, 0xc1, 0x46]);
+        assert!(SoftwareHmac.context(HmacAlgorithm::Sha512, b"").is_err());
+    }
 }
@@ -1,7 +1,11 @@
 use crate::result::OutOfRangeError;
 use crate::result::TockResult;
 use crate::syscalls::command;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
 use core::marker::PhantomData;
+use core::str::Chars;
 
 const DRIVER_NUMBER: usize = 0x00002;
 
@@ -128,13 +132,276 @@ impl From<bool> for LedState {
     }
 }
 
+/// Gap between the blinks of a digit and between Morse symbols, in units
+const SYMBOL_GAP: usize = 1;
+/// Gap between digits and between Morse letters, in units
+const LETTER_GAP: usize = 3;
+/// Gap after a blink code and between Morse words, in units
+const WORD_GAP: usize = 7;
+const DASH: usize = 3;
+
+/// Plays status signals on a LED, either as blink codes or as Morse code.
+///
+/// All durations are multiples of a configurable time unit. The blocking
+/// methods busy wait and do not need an activated timer driver, so they can
+/// be used where futures cannot be driven anymore, e.g. in a panic handler.
+///
+/// Example usage (in a custom panic handler):
+/// ```no_run
+/// # use libtock::leds::Signaler;
+/// # use libtock::timer::Duration;
+/// # fn doc() -> ! {
+/// let mut drivers = unsafe { libtock::drivers::retrieve_drivers_unsafe() };
+/// if let Ok(leds_driver) = drivers.leds.init_driver() {
+///     if let Ok(led) = leds_driver.get(0) {
+///         let signaler = Signaler::new(led, Duration::from_ms(200));
+///         loop {
+///             let _ = signaler.blink_code_blocking(42);
+///         }
+///     }
+/// }
+/// loop {}
+/// # }
+/// ```
+pub struct Signaler<'a> {
+    led: Led<'a>,
+    unit: Duration<usize>,
+}
+
+impl<'a> Signaler<'a> {
+    pub fn new(led: Led<'a>, unit: Duration<usize>) -> Signaler<'a> {
+        Signaler { led, unit }
+    }
+
+    pub fn led(&self) -> &Led<'a> {
+        &self.led
+    }
+
+    pub fn unit(&self) -> Duration<usize> {
+        self.unit
+    }
+
+    /// Plays `code` as groups of short blinks, one group per decimal digit
+    /// with ten blinks for 0, see [BlinkCode]
+    pub async fn blink_code(&self, timer: &ParallelSleepDriver<'_>, code: usize) -> TockResult<()> {
+        self.play(timer, BlinkCode::new(code)).await
+    }
+
+    /// Plays `text` as Morse code, see [Morse]
+    pub async fn morse(&self, timer: &ParallelSleepDriver<'_>, text: &str) -> TockResult<()> {
+        self.play(timer, Morse::new(text)).await
+    }
+
+    pub async fn play(
+        &self,
+        timer: &ParallelSleepDriver<'_>,
+        pulses: impl Iterator<Item = Pulse>,
+    ) -> TockResult<()> {
+        for pulse in pulses {
+            self.led.set(pulse.on)?;
+            timer.sleep(self.pulse_duration(pulse)).await?;
+        }
+        Ok(())
+    }
+
+    /// Like [blink_code()](Self::blink_code), but busy waits
+    pub fn blink_code_blocking(&self, code: usize) -> TockResult<()> {
+        self.play_blocking(BlinkCode::new(code))
+    }
+
+    /// Like [morse()](Self::morse), but busy waits
+    pub fn morse_blocking(&self, text: &str) -> TockResult<()> {
+        self.play_blocking(Morse::new(text))
+    }
+
+    pub fn play_blocking(&self, pulses: impl Iterator<Item = Pulse>) -> TockResult<()> {
+        for pulse in pulses {
+            self.led.set(pulse.on)?;
+            timer::busy_wait(self.pulse_duration(pulse))?;
+        }
+        Ok(())
+    }
+
+    fn pulse_duration(&self, pulse: Pulse) -> Duration<usize> {
+        Duration::from_ms(self.unit.ms().saturating_mul(pulse.units))
+    }
+}
+
+/// The LED is kept on or off for `units` time units
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Pulse {
+    pub on: bool,
+    pub units: usize,
+}
+
+impl Pulse {
+    fn on(units: usize) -> Pulse {
+        Pulse { on: true, units }
+    }
+
+    fn off(units: usize) -> Pulse {
+        Pulse { on: false, units }
+    }
+}
+
+/// Pulses of a numeric blink code. Each decimal digit is shown as that many
+/// blinks, 0 as ten blinks, starting with the most significant digit. The
+/// code ends with a long pause so repetitions can be told apart.
+pub struct BlinkCode {
+    digits: [u8; 20],
+    remaining_digits: usize,
+    remaining_blinks: u8,
+    pending_gap: Option<usize>,
+}
+
+impl BlinkCode {
+    pub fn new(code: usize) -> BlinkCode {
+        let mut digits = [0; 20];
+        let mut remaining_digits = 0;
+        let mut rest = code;
+        loop {
+            digits[remaining_digits] = (rest % 10) as u8;
+            remaining_digits += 1;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        BlinkCode {
+            digits,
+            remaining_digits,
+            remaining_blinks: 0,
+            pending_gap: None,
+        }
+    }
+}
+
+impl Iterator for BlinkCode {
+    type Item = Pulse;
+
+    fn next(&mut self) -> Option<Pulse> {
+        if let Some(units) = self.pending_gap.take() {
+            return Some(Pulse::off(units));
+        }
+        if self.remaining_blinks == 0 {
+            if self.remaining_digits == 0 {
+                return None;
+            }
+            self.remaining_digits -= 1;
+            self.remaining_blinks = match self.digits[self.remaining_digits] {
+                0 => 10,
+                digit => digit,
+            };
+        }
+        self.remaining_blinks -= 1;
+        self.pending_gap = Some(if self.remaining_blinks > 0 {
+            SYMBOL_GAP
+        } else if self.remaining_digits > 0 {
+            LETTER_GAP
+        } else {
+            WORD_GAP
+        });
+        Some(Pulse::on(1))
+    }
+}
+
+/// Pulses of a text in international Morse code. Letters and digits are
+/// supported, other characters except spaces are skipped.
+pub struct Morse<'a> {
+    chars: Chars<'a>,
+    symbols: &'static [u8],
+    pending_gap: Option<usize>,
+}
+
+impl<'a> Morse<'a> {
+    pub fn new(text: &'a str) -> Morse<'a> {
+        Morse {
+            chars: text.chars(),
+            symbols: &[],
+            pending_gap: None,
+        }
+    }
+}
+
+impl<'a> Iterator for Morse<'a> {
+    type Item = Pulse;
+
+    fn next(&mut self) -> Option<Pulse> {
+        if let Some(units) = self.pending_gap.take() {
+            return Some(Pulse::off(units));
+        }
+        while self.symbols.is_empty() {
+            match self.chars.next()? {
+                ' ' => return Some(Pulse::off(WORD_GAP - LETTER_GAP)),
+                c => self.symbols = morse_code(c).unwrap_or(&[]),
+            }
+        }
+        let symbol = self.symbols[0];
+        self.symbols = &self.symbols[1..];
+        self.pending_gap = Some(if self.symbols.is_empty() {
+            LETTER_GAP
+        } else {
+            SYMBOL_GAP
+        });
+        Some(Pulse::on(if symbol == b'-' { DASH } else { 1 }))
+    }
+}
+
+fn morse_code(c: char) -> Option<&'static [u8]> {
+    let code: &[u8] = match c.to_ascii_uppercase() {
+        'A' => b".-",
+        'B' => b"-...",
+        'C' => b"-.-.",
+        'D' => b"-..",
+        'E' => b".",
+        'F' => b"..-.",
+        'G' => b"--.",
+        'H' => b"....",
+        'I' => b"..",
+        'J' => b".---",
+        'K' => b"-.-",
+        'L' => b".-..",
+        'M' => b"--",
+        'N' => b"-.",
+        'O' => b"---",
+        'P' => b".--.",
+        'Q' => b"--.-",
+        'R' => b".-.",
+        'S' => b"...",
+        'T' => b"-",
+        'U' => b"..-",
+        'V' => b"...-",
+        'W' => b".--",
+        'X' => b"-..-",
+        'Y' => b"-.--",
+        'Z' => b"--..",
+        '0' => b"-----",
+        '1' => b".----",
+        '2' => b"..---",
+        '3' => b"...--",
+        '4' => b"....-",
+        '5' => b".....",
+        '6' => b"-....",
+        '7' => b"--...",
+        '8' => b"---..",
+        '9' => b"----.",
+        _ => return None,
+    };
+    Some(code)
+}
+
 #[cfg(test)]
 mod test {
     use super::command_nr;
+    use super::BlinkCode;
+    use super::Morse;
+    use super::Pulse;
+    use super::Signaler;
     use super::DRIVER_NUMBER;
     use crate::result::TockResult;
     use crate::syscalls;
     use crate::syscalls::raw::Event;
+    use crate::timer::Duration;
 
     #[test]
     pub fn single_led_can_be_enabled() {
@@ -158,4 +425,67 @@ mod test {
             ]
         );
     }
+
+    fn pulses(iterator: impl Iterator<Item = Pulse>) -> Vec<(bool, usize)> {
+        iterator.map(|pulse| (pulse.on, pulse.units)).collect()
+    }
+
+    #[test]
+    pub fn blink_code_shows_digits_in_order() {
+        assert_eq!(
+            pulses(BlinkCode::new(21)),
+            [
+                (true, 1),
+                (false, 1),
+                (true, 1),
+                (false, 3),
+                (true, 1),
+                (false, 7),
+            ]
+        );
+        assert_eq!(pulses(BlinkCode::new(0)).len(), 20);
+    }
+
+    #[test]
+    pub fn morse_separates_symbols_letters_and_words() {
+        assert_eq!(
+            pulses(Morse::new("a e?")),
+            [
+                (true, 1),
+                (false, 1),
+                (true, 3),
+                (false, 3),
+                (false, 4),
+                (true, 1),
+                (false, 3),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn blocking_signal_switches_led() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(1);
+            let leds_driver = drivers.leds.init_driver()?;
+            next_return.set(0);
+            let signaler = Signaler::new(leds_driver.get(0)?, Duration::from_ms(100));
+            signaler.morse_blocking("e")
+        });
+        let switches: Vec<_> = events
+            .into_iter()
+            .filter(|event| match event {
+                Event::Command(DRIVER_NUMBER, _, _, _) => true,
+                _ => false,
+            })
+            .collect();
+        assert_eq!(
+            switches,
+            vec![
+                Event::Command(DRIVER_NUMBER, command_nr::COUNT, 0, 0),
+                Event::Command(DRIVER_NUMBER, command_nr::ON, 0, 0),
+                Event::Command(DRIVER_NUMBER, command_nr::OFF, 0, 0),
+            ]
+        );
+    }
 }
@@ -1,21 +1,36 @@
 #![cfg_attr(not(test), no_std)]
+// Const generics let drivers like `gpio::Bus` take arrays of any length
+// without an allocator. The feature is still marked incomplete, so its
+// warning is silenced.
+#![feature(const_generics)]
+#![allow(incomplete_features)]
 
 pub mod adc;
+pub mod aes;
 pub mod ble_composer;
 pub mod ble_parser;
 pub mod buttons;
 pub mod console;
+mod crypto;
+pub mod debounce;
 pub mod debug;
 pub mod drivers;
+pub mod dsp;
 pub mod electronics;
+pub mod event_queue;
 pub mod executor;
 pub mod futures;
+pub mod gesture;
 pub mod gpio;
 pub mod hmac;
 pub mod leds;
+pub mod pwm;
 pub mod result;
 pub mod rng;
 pub mod sensors;
+pub mod sha;
+#[cfg(feature = "software_hmac")]
+pub mod sha256;
 pub mod simple_ble;
 pub mod temperature;
 pub mod timer;
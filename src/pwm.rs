@@ -0,0 +1,455 @@
+//! Software PWM for outputs that can only be switched on and off, like the
+//! LEDs of the LED driver.
+//!
+//! [SoftPwm] switches its channels on at the start of every period and off
+//! again once their duty cycle has elapsed. The time up to each switching
+//! point is slept in whole milliseconds, so other tasks keep running, and
+//! only the sub-millisecond rest is busy waited for accuracy. The resolution
+//! is limited by the timer frequency and the syscall overhead, so periods of
+//! a few milliseconds work best.
+
+use crate::gpio::GpioWrite;
+use crate::leds::Led;
+use crate::result::OutOfRangeError;
+use crate::result::TockResult;
+use crate::timer;
+use crate::timer::Duration;
+use crate::timer::ParallelSleepDriver;
+
+/// Duty cycle of an output that is always on
+pub const MAX_DUTY: u8 = 255;
+
+/// An output that can be switched on and off, e.g. a [Led] or a [GpioWrite].
+pub trait PwmOutput {
+    fn switch(&self, on: bool) -> TockResult<()>;
+}
+
+impl<'a> PwmOutput for Led<'a> {
+    fn switch(&self, on: bool) -> TockResult<()> {
+        if on {
+            self.on()
+        } else {
+            self.off()
+        }
+    }
+}
+
+impl<'a> PwmOutput for GpioWrite<'a> {
+    fn switch(&self, on: bool) -> TockResult<()> {
+        if on {
+            self.set_high()
+        } else {
+            self.set_low()
+        }
+    }
+}
+
+impl<T: PwmOutput> PwmOutput for &T {
+    fn switch(&self, on: bool) -> TockResult<()> {
+        (**self).switch(on)
+    }
+}
+
+/// Timer-driven PWM on `N` channels with independent duty cycles.
+///
+/// Example usage (dim two LEDs):
+/// ```no_run
+/// # use libtock::pwm::SoftPwm;
+/// # use libtock::result::TockResult;
+/// # use libtock::timer::Duration;
+/// # async fn doc() -> TockResult<()> {
+/// let mut drivers = libtock::retrieve_drivers()?;
+/// let leds_driver = drivers.leds.init_driver()?;
+/// let mut timer_driver = drivers.timer.create_timer_driver();
+/// let timer_driver = timer_driver.activate()?;
+///
+/// let leds = [leds_driver.get(0)?, leds_driver.get(1)?];
+/// let mut pwm = SoftPwm::new(leds, &timer_driver, Duration::from_ms(10))?;
+/// pwm.set_duty(0, 20)?;
+/// pwm.set_duty(1, 200)?;
+/// pwm.run_for(Duration::from_ms(1000)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SoftPwm<'a, P, const N: usize> {
+    channels: [P; N],
+    duty_cycles: [u8; N],
+    timer: &'a ParallelSleepDriver<'a>,
+    period: Duration<usize>,
+    freq: usize,
+}
+
+impl<'a, P: PwmOutput, const N: usize> SoftPwm<'a, P, N> {
+    /// Creates the engine with all duty cycles set to 0. Periods below 1 ms
+    /// are rounded up.
+    pub fn new(
+        channels: [P; N],
+        timer: &'a ParallelSleepDriver<'a>,
+        period: Duration<usize>,
+    ) -> TockResult<SoftPwm<'a, P, N>> {
+        let freq = timer.get_current_clock()?.clock_frequency().hz();
+        Ok(SoftPwm {
+            channels,
+            duty_cycles: [0; N],
+            timer,
+            period: Duration::from_ms(period.ms().max(1)),
+            freq,
+        })
+    }
+
+    pub fn channels(&self) -> &[P] {
+        &self.channels
+    }
+
+    pub fn period(&self) -> Duration<usize> {
+        self.period
+    }
+
+    pub fn duty(&self, channel: usize) -> Result<u8, OutOfRangeError> {
+        self.duty_cycles
+            .get(channel)
+            .copied()
+            .ok_or(OutOfRangeError)
+    }
+
+    /// Sets the on time of `channel` to `duty / MAX_DUTY` of the period. Takes
+    /// effect with the next period.
+    pub fn set_duty(&mut self, channel: usize, duty: u8) -> Result<(), OutOfRangeError> {
+        *self.duty_cycles.get_mut(channel).ok_or(OutOfRangeError)? = duty;
+        Ok(())
+    }
+
+    /// Runs a single period
+    pub async fn run_cycle(&self) -> TockResult<()> {
+        let start = self.on_phase().await?;
+        let period_ticks = timer::ms_to_ticks(self.period.ms(), self.freq);
+        self.timer.wait_until_ticks(start, period_ticks).await
+    }
+
+    /// Runs full periods until `duration` has passed
+    pub async fn run_for(&self, duration: Duration<usize>) -> TockResult<()> {
+        for _ in 0..duration.ms() / self.period.ms() {
+            self.run_cycle().await?;
+        }
+        Ok(())
+    }
+
+    /// Plays `effect` on `channel` `repetitions` times. The duty cycle is
+    /// updated every period, the other channels keep running unchanged.
+    pub async fn play(
+        &mut self,
+        channel: usize,
+        effect: &LedEffect<'_>,
+        repetitions: usize,
+    ) -> TockResult<()> {
+        if channel >= N {
+            return Err(OutOfRangeError.into());
+        }
+        let cycles = effect.duration().ms().saturating_mul(repetitions) / self.period.ms();
+        for cycle in 0..cycles {
+            self.duty_cycles[channel] = effect.brightness_at(cycle * self.period.ms());
+            self.run_cycle().await?;
+        }
+        self.duty_cycles[channel] = effect.final_brightness();
+        Ok(())
+    }
+
+    /// Switches all channels with a duty cycle above 0 on and waits until
+    /// the last channel below [MAX_DUTY] has been switched off again. Returns
+    /// the clock value at the start of the period.
+    async fn on_phase(&self) -> TockResult<usize> {
+        let start = self.now()?;
+        for (channel, &duty) in self.channels.iter().zip(self.duty_cycles.iter()) {
+            channel.switch(duty > 0)?;
+        }
+        let period_ticks = timer::ms_to_ticks(self.period.ms(), self.freq);
+        switch_off_in_order(self.timer, start, period_ticks, self.duty_cycles, |index| {
+            self.channels[index].switch(false)
+        })
+        .await?;
+        Ok(start)
+    }
+
+    fn now(&self) -> TockResult<usize> {
+        Ok(self.timer.get_current_clock()?.num_ticks() as usize)
+    }
+}
+
+/// Switches off output `index` with `switch_off` once its on time of
+/// `duty_cycles[index] / MAX_DUTY` of `period_ticks` has passed since
+/// `start`, shortest on time first. Outputs that are always off or always on
+/// are skipped.
+pub(crate) async fn switch_off_in_order<F: FnMut(usize) -> TockResult<()>, const N: usize>(
+    timer: &ParallelSleepDriver<'_>,
+    start: usize,
+    period_ticks: usize,
+    duty_cycles: [u8; N],
+    mut switch_off: F,
+) -> TockResult<()> {
+    let mut order = [0; N];
+    for (index, entry) in order.iter_mut().enumerate() {
+        *entry = index;
+    }
+    order.sort_unstable_by_key(|&index| duty_cycles[index]);
+    for &index in order.iter() {
+        let duty = duty_cycles[index];
+        if duty == 0 || duty == MAX_DUTY {
+            continue;
+        }
+        // Multiplied in u64, as period_ticks * MAX_DUTY overflows 32 bits
+        // for long periods or fast clocks
+        let on_ticks = period_ticks as u64 * u64::from(duty) / u64::from(MAX_DUTY);
+        timer.wait_until_ticks(start, on_ticks as usize).await?;
+        switch_off(index)?;
+    }
+    Ok(())
+}
+
+/// Brightness curves for [SoftPwm::play()]
+#[derive(Copy, Clone, Debug)]
+pub enum LedEffect<'a> {
+    /// Fades in and out once per `period`
+    Breathe { period: Duration<usize> },
+    /// Changes the brightness from `from` to `to` and keeps it there
+    Fade {
+        from: u8,
+        to: u8,
+        duration: Duration<usize>,
+    },
+    /// Alternates between full brightness and off, starting with an on time
+    Blink { pattern: &'a [Duration<usize>] },
+}
+
+impl<'a> LedEffect<'a> {
+    /// Length of a single repetition
+    pub fn duration(&self) -> Duration<usize> {
+        match *self {
+            LedEffect::Breathe { period } => period,
+            LedEffect::Fade { duration, .. } => duration,
+            LedEffect::Blink { pattern } => {
+                Duration::from_ms(pattern.iter().map(|duration| duration.ms()).sum())
+            }
+        }
+    }
+
+    /// The duty cycle `elapsed_ms` after the start of the effect. Brightness
+    /// levels of breathing and fading are gamma corrected so the changes
+    /// appear uniform.
+    pub fn brightness_at(&self, elapsed_ms: usize) -> u8 {
+        match *self {
+            LedEffect::Breathe { period } => {
+                let period = period.ms().max(2);
+                let half = period / 2;
+                let t = elapsed_ms % period;
+                let level = if t < half {
+                    t * 255 / half
+                } else {
+                    (period - t) * 255 / (period - half)
+                };
+                gamma_correct(level as u8)
+            }
+            LedEffect::Fade { from, to, duration } => {
+                let duration = duration.ms();
+                let level = if elapsed_ms >= duration {
+                    to as isize
+                } else {
+                    let delta = to as isize - from as isize;
+                    from as isize + delta * elapsed_ms as isize / duration as isize
+                };
+                gamma_correct(level as u8)
+            }
+            LedEffect::Blink { pattern } => {
+                let total = self.duration().ms();
+                if total == 0 {
+                    return 0;
+                }
+                let mut t = elapsed_ms % total;
+                for (index, duration) in pattern.iter().enumerate() {
+                    if t < duration.ms() {
+                        return if index % 2 == 0 { MAX_DUTY } else { 0 };
+                    }
+                    t -= duration.ms();
+                }
+                0
+            }
+        }
+    }
+
+    /// The duty cycle kept after the effect has been played
+    fn final_brightness(&self) -> u8 {
+        match *self {
+            LedEffect::Fade { to, .. } => gamma_correct(to),
+            _ => 0,
+        }
+    }
+}
+
+/// Maps a perceived brightness level to a duty cycle
+pub fn gamma_correct(level: u8) -> u8 {
+    (level as usize * level as usize / MAX_DUTY as usize) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor;
+    use crate::gpio;
+    use crate::gpio::GpioWrite;
+    use crate::syscalls;
+    use crate::syscalls::raw::Event;
+    use crate::syscalls::raw::NextReturn;
+    use core::cell::Cell;
+    use core::task::Poll;
+
+    /// Runs `f` with four GPIO channels at the clock frequency `freq`. The
+    /// clock value equals `freq` while `f` runs.
+    fn with_pwm<F>(freq: isize, f: F) -> Vec<Event>
+    where
+        F: Fn(&mut SoftPwm<GpioWrite, 4>, &NextReturn) -> TockResult<()>,
+    {
+        let checked = Cell::new(false);
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let mut timer_driver = drivers.timer.create_timer_driver();
+            let timer_driver = timer_driver.activate()?;
+            next_return.set(4);
+            let mut gpio_driver = drivers.gpio.init_driver()?;
+            next_return.set(0);
+            let mut gpios = gpio_driver.gpios();
+            let (mut gpio0, mut gpio1, mut gpio2, mut gpio3) = (
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+                gpios.next().unwrap(),
+            );
+            let pins = [
+                gpio0.enable_output()?,
+                gpio1.enable_output()?,
+                gpio2.enable_output()?,
+                gpio3.enable_output()?,
+            ];
+            next_return.set(freq);
+            let mut pwm = SoftPwm::new(pins, &timer_driver, Duration::from_ms(10))?;
+            f(&mut pwm, next_return)?;
+            checked.set(true);
+            core::mem::forget(pwm);
+            Ok(())
+        });
+        assert!(checked.get());
+        events
+    }
+
+    #[test]
+    pub fn channels_are_switched_off_in_order_of_duty() {
+        let events = with_pwm(0, |pwm, _| {
+            pwm.set_duty(0, 200)?;
+            pwm.set_duty(1, 0)?;
+            pwm.set_duty(2, MAX_DUTY)?;
+            pwm.set_duty(3, 50)?;
+            let on_phase = pwm.on_phase();
+            ::futures::pin_mut!(on_phase);
+            match executor::poll(on_phase) {
+                Poll::Ready(result) => result.map(|_| ()),
+                Poll::Pending => panic!("on phase did not complete"),
+            }
+        });
+        let switches: Vec<_> = events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Command(gpio::DRIVER_NUMBER, command, gpio_num, _)
+                    if command == gpio::command_nr::SET_HIGH
+                        || command == gpio::command_nr::SET_LOW =>
+                {
+                    Some((gpio_num, command == gpio::command_nr::SET_HIGH))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            switches,
+            [
+                (0, true),
+                (1, false),
+                (2, true),
+                (3, true),
+                (3, false),
+                (0, false),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn duty_time_is_slept() {
+        // At 1 kHz a duty of 200 keeps channel 0 on for 7 of the 10 ticks
+        let events = with_pwm(1000, |pwm, _| {
+            pwm.set_duty(0, 200)?;
+            let on_phase = pwm.on_phase();
+            ::futures::pin_mut!(on_phase);
+            assert!(executor::poll(on_phase).is_pending());
+            Ok(())
+        });
+        assert!(events.iter().any(|event| match event {
+            Event::Command(timer::DRIVER_NUMBER, timer::command_nr::SET_ALARM, _, _) => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    pub fn invalid_channels_are_rejected() {
+        with_pwm(0, |pwm, _| {
+            assert!(pwm.set_duty(4, 10).is_err());
+            assert!(pwm.duty(4).is_err());
+            assert_eq!(pwm.duty(3).ok(), Some(0));
+            let effect = LedEffect::Fade {
+                from: 0,
+                to: MAX_DUTY,
+                duration: Duration::from_ms(100),
+            };
+            let play = pwm.play(4, &effect, 1);
+            ::futures::pin_mut!(play);
+            assert!(match executor::poll(play) {
+                Poll::Ready(result) => result.is_err(),
+                Poll::Pending => false,
+            });
+            Ok(())
+        });
+    }
+
+    #[test]
+    pub fn breathing_rises_and_falls() {
+        let effect = LedEffect::Breathe {
+            period: Duration::from_ms(1000),
+        };
+        assert_eq!(effect.brightness_at(0), 0);
+        assert!(effect.brightness_at(250) < effect.brightness_at(400));
+        assert_eq!(effect.brightness_at(500), MAX_DUTY);
+        assert!(effect.brightness_at(750) < effect.brightness_at(600));
+        assert_eq!(effect.brightness_at(1000), 0);
+    }
+
+    #[test]
+    pub fn fading_ends_at_target() {
+        let effect = LedEffect::Fade {
+            from: MAX_DUTY,
+            to: 0,
+            duration: Duration::from_ms(100),
+        };
+        assert_eq!(effect.brightness_at(0), MAX_DUTY);
+        assert_eq!(effect.brightness_at(50), gamma_correct(128));
+        assert_eq!(effect.brightness_at(500), 0);
+        assert_eq!(effect.final_brightness(), 0);
+    }
+
+    #[test]
+    pub fn blink_pattern_repeats() {
+        let pattern = [Duration::from_ms(100), Duration::from_ms(50)];
+        let effect = LedEffect::Blink { pattern: &pattern };
+        assert_eq!(effect.duration().ms(), 150);
+        let samples: Vec<_> = [0, 99, 100, 149, 150, 260]
+            .iter()
+            .map(|&t| effect.brightness_at(t))
+            .collect();
+        assert_eq!(samples, [MAX_DUTY, MAX_DUTY, 0, 0, MAX_DUTY, 0]);
+    }
+}
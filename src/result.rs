@@ -50,6 +50,22 @@ pub enum OtherError {
     GpioDriverInvalidState,
     TimerDriverDurationOutOfRange,
     TimerDriverErroneousClockFrequency,
+    I2cAddressNotAcknowledged,
+    I2cDataNotAcknowledged,
+    I2cClockStretchTimeout,
+    OneWireNoDevicePresent,
+    OneWireCrcMismatch,
+    Ds18b20ConversionTimeout,
+    UltrasonicEchoTimeout,
+    HmacKeyTooLong,
+    HmacDataTooLong,
+    HmacFailed,
+    HmacAlgorithmNotSupported,
+    ShaFailed,
+    AesInvalidLength,
+    AesDataTooLong,
+    AesFailed,
+    AesAuthenticationFailed,
     DriversAlreadyTaken,
     OutOfRange,
 }
@@ -0,0 +1,351 @@
+use crate::crypto;
+use crate::result::OtherError;
+use crate::result::TockResult;
+use crate::result::SUCCESS;
+use crate::syscalls;
+use core::fmt;
+use core::marker::PhantomData;
+
+const DRIVER_NUMBER: usize = 0x40005;
+
+pub const DATA_BUFFER_SIZE: usize = 256;
+
+mod command_nr {
+    pub const IS_DRIVER_AVAILABLE: usize = 0;
+    pub const SET_ALGORITHM: usize = 1;
+    pub const RUN: usize = 2;
+    pub const UPDATE: usize = 3;
+    pub const FINISH: usize = 4;
+}
+
+mod allow_nr {
+    pub const DATA: usize = 1;
+    pub const DEST: usize = 2;
+}
+
+/// Digest of `N` bytes
+#[derive(Copy, Clone)]
+pub struct Digest<const N: usize> {
+    bytes: [u8; N],
+}
+
+pub type Sha256Digest = Digest<32>;
+pub type Sha384Digest = Digest<48>;
+pub type Sha512Digest = Digest<64>;
+
+impl<const N: usize> Default for Digest<N> {
+    fn default() -> Self {
+        Digest { bytes: [0; N] }
+    }
+}
+
+impl<const N: usize> Digest<N> {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> [u8; N] {
+        self.bytes
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for Digest<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<const N: usize> AsMut<[u8]> for Digest<N> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl<const N: usize> PartialEq for Digest<N> {
+    fn eq(&self, other: &Digest<N>) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<const N: usize> Eq for Digest<N> {}
+
+impl<const N: usize> fmt::Debug for Digest<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        crypto::fmt_hex(self.as_bytes(), f)
+    }
+}
+
+/// Hash function supported by the SHA driver, determining the digest type
+pub trait ShaAlgorithm: Copy {
+    type Digest: Copy + Default + AsMut<[u8]>;
+
+    /// Identifier passed to the kernel
+    const ID: usize;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Sha256;
+
+impl ShaAlgorithm for Sha256 {
+    type Digest = Sha256Digest;
+    const ID: usize = 0;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Sha384;
+
+impl ShaAlgorithm for Sha384 {
+    type Digest = Sha384Digest;
+    const ID: usize = 1;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Sha512;
+
+impl ShaAlgorithm for Sha512 {
+    type Digest = Sha512Digest;
+    const ID: usize = 2;
+}
+
+#[non_exhaustive]
+pub struct ShaDriverFactory;
+
+impl ShaDriverFactory {
+    pub fn init_driver(&mut self) -> TockResult<ShaDriver> {
+        syscalls::command(DRIVER_NUMBER, command_nr::IS_DRIVER_AVAILABLE, 0, 0)?;
+        let sha = ShaDriver {
+            lifetime: PhantomData,
+        };
+        Ok(sha)
+    }
+}
+
+pub struct ShaDriver<'a> {
+    lifetime: PhantomData<&'a ()>,
+}
+
+impl<'a> ShaDriver<'a> {
+    /// Hashes `data` of any length. Data longer than [DATA_BUFFER_SIZE] is
+    /// passed to the kernel in chunks.
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use libtock::result::TockResult;
+    /// # use libtock::sha::Sha256;
+    /// # async fn doc() -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let sha_driver = drivers.sha.init_driver()?;
+    /// let digest = sha_driver.digest(Sha256, b"message").await?;
+    /// let _bytes: [u8; 32] = digest.into_bytes();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn digest<A: ShaAlgorithm>(
+        &self,
+        algorithm: A,
+        data: &[u8],
+    ) -> TockResult<A::Digest> {
+        if data.len() > DATA_BUFFER_SIZE {
+            let mut context = self.context(algorithm);
+            context.update(data).await?;
+            return context.finalize().await;
+        }
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+        let data_buffer = &mut data_buffer[..data.len()];
+        data_buffer.copy_from_slice(data);
+        let mut digest = A::Digest::default();
+        self.execute(
+            command_nr::RUN,
+            Some(A::ID),
+            Some(data_buffer),
+            Some(digest.as_mut()),
+        )
+        .await?;
+        Ok(digest)
+    }
+
+    /// Starts an incremental hash computation
+    ///
+    /// Example usage:
+    /// ```no_run
+    /// # use libtock::result::TockResult;
+    /// # use libtock::sha::Sha512;
+    /// # async fn doc(image: &[u8], log: &[u8]) -> TockResult<()> {
+    /// let mut drivers = libtock::retrieve_drivers()?;
+    /// let sha_driver = drivers.sha.init_driver()?;
+    /// let mut context = sha_driver.context(Sha512);
+    /// context.update(image).await?;
+    /// context.update(log).await?;
+    /// let _digest = context.finalize().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn context<A: ShaAlgorithm>(&self, algorithm: A) -> ShaContext<A> {
+        ShaContext {
+            driver: self,
+            algorithm,
+            configured: false,
+        }
+    }
+
+    /// Shares the buffers with the kernel, selects the algorithm if
+    /// `algorithm_id` is given, runs `command` and waits for its completion
+    async fn execute(
+        &self,
+        command: usize,
+        algorithm_id: Option<usize>,
+        data: Option<&mut [u8]>,
+        dest: Option<&mut [u8]>,
+    ) -> TockResult<()> {
+        let commands = [
+            (
+                command_nr::SET_ALGORITHM,
+                algorithm_id.unwrap_or_default(),
+                0,
+            ),
+            (command, 0, 0),
+        ];
+        let commands = match algorithm_id {
+            Some(_) => &commands[..],
+            None => &commands[1..],
+        };
+        let (result_code, _) = crypto::run(
+            DRIVER_NUMBER,
+            &mut [(allow_nr::DATA, data), (allow_nr::DEST, dest)],
+            commands,
+        )
+        .await?;
+        match result_code {
+            SUCCESS => Ok(()),
+            _ => Err(OtherError::ShaFailed.into()),
+        }
+    }
+}
+
+/// Incremental hash computation created by [ShaDriver::context()]. The data
+/// is passed to the kernel in chunks of [DATA_BUFFER_SIZE] bytes.
+pub struct ShaContext<'a, A: ShaAlgorithm> {
+    driver: &'a ShaDriver<'a>,
+    algorithm: A,
+    configured: bool,
+}
+
+impl<'a, A: ShaAlgorithm> ShaContext<'a, A> {
+    pub fn algorithm(&self) -> A {
+        self.algorithm
+    }
+
+    /// Algorithm to select with the next command. The algorithm is only
+    /// selected with the first command of the computation.
+    fn setup(&self) -> Option<usize> {
+        if self.configured {
+            None
+        } else {
+            Some(A::ID)
+        }
+    }
+
+    /// Appends `data` to the message
+    pub async fn update(&mut self, data: &[u8]) -> TockResult<()> {
+        for chunk in data.chunks(DATA_BUFFER_SIZE) {
+            let mut data_buffer = [0; DATA_BUFFER_SIZE];
+            let data_buffer = &mut data_buffer[..chunk.len()];
+            data_buffer.copy_from_slice(chunk);
+            self.driver
+                .execute(command_nr::UPDATE, self.setup(), Some(data_buffer), None)
+                .await?;
+            self.configured = true;
+        }
+        Ok(())
+    }
+
+    /// Completes the computation and returns the digest
+    pub async fn finalize(self) -> TockResult<A::Digest> {
+        let mut digest = A::Digest::default();
+        self.driver
+            .execute(
+                command_nr::FINISH,
+                self.setup(),
+                None,
+                Some(digest.as_mut()),
+            )
+            .await?;
+        Ok(digest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor;
+
+    #[test]
+    pub fn short_data_is_hashed_at_once() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let sha_driver = drivers.sha.init_driver()?;
+            let digest = sha_driver.digest(Sha384, b"abc");
+            ::futures::pin_mut!(digest);
+            assert!(executor::poll(digest).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            crypto::summarize(DRIVER_NUMBER, events)[..6],
+            [
+                (2, command_nr::IS_DRIVER_AVAILABLE, 0, 0),
+                (0, allow_nr::DATA, 3, 0),
+                (0, allow_nr::DEST, 48, 0),
+                (1, crypto::SUBSCRIBE_CALLBACK, 0, 0),
+                (2, command_nr::SET_ALGORITHM, Sha384::ID, 0),
+                (2, command_nr::RUN, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn long_data_is_hashed_in_chunks() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let sha_driver = drivers.sha.init_driver()?;
+            let data = [0; DATA_BUFFER_SIZE + 1];
+            let digest = sha_driver.digest(Sha256, &data);
+            ::futures::pin_mut!(digest);
+            assert!(executor::poll(digest).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            crypto::summarize(DRIVER_NUMBER, events)[1..5],
+            [
+                (0, allow_nr::DATA, DATA_BUFFER_SIZE, 0),
+                (1, crypto::SUBSCRIBE_CALLBACK, 0, 0),
+                (2, command_nr::SET_ALGORITHM, Sha256::ID, 0),
+                (2, command_nr::UPDATE, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn configured_context_only_passes_data() {
+        let events = syscalls::raw::run_recording_events::<TockResult<()>, _>(|next_return| {
+            let mut drivers = unsafe { crate::drivers::retrieve_drivers_unsafe() };
+            next_return.set(0);
+            let sha_driver = drivers.sha.init_driver()?;
+            let mut context = sha_driver.context(Sha256);
+            context.configured = true;
+            let update = context.update(b"message");
+            ::futures::pin_mut!(update);
+            assert!(executor::poll(update).is_pending());
+            Ok(())
+        });
+        assert_eq!(
+            crypto::summarize(DRIVER_NUMBER, events)[1..4],
+            [
+                (0, allow_nr::DATA, 7, 0),
+                (1, crypto::SUBSCRIBE_CALLBACK, 0, 0),
+                (2, command_nr::UPDATE, 0, 0),
+            ]
+        );
+    }
+}
@@ -61,6 +61,10 @@ pub struct Temperature {
 }
 
 impl Temperature {
+    pub const fn from_centi_celsius(centi_celsius: isize) -> Temperature {
+        Temperature { centi_celsius }
+    }
+
     pub fn in_celsius(self) -> isize {
         self.centi_celsius / 100
     }
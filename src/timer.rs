@@ -13,9 +13,9 @@ use core::isize;
 use core::marker::PhantomData;
 use core::ops::{Add, AddAssign, Sub};
 
-const DRIVER_NUMBER: usize = 0x00000;
+pub(crate) const DRIVER_NUMBER: usize = 0x00000;
 
-mod command_nr {
+pub(crate) mod command_nr {
     pub const IS_DRIVER_AVAILABLE: usize = 0;
     pub const GET_CLOCK_FREQUENCY: usize = 1;
     pub const GET_CLOCK_VALUE: usize = 2;
@@ -158,6 +158,10 @@ impl ClockValue {
         self.num_ticks
     }
 
+    pub fn clock_frequency(self) -> ClockFrequency {
+        self.clock_frequency
+    }
+
     pub fn ms(self) -> isize {
         if self.num_ticks.abs() < isize::MAX / 1000 {
             (1000 * self.num_ticks) / self.clock_frequency.hz() as isize
@@ -379,6 +383,16 @@ impl<'a> TimerDriver<'a> {
 }
 
 impl<'a> ParallelSleepDriver<'a> {
+    /// Returns the current value of the kernel's clock
+    pub fn get_current_clock(&self) -> TockResult<ClockValue> {
+        Ok(ClockValue {
+            num_ticks: get_current_ticks()? as isize,
+            clock_frequency: ClockFrequency {
+                hz: get_clock_frequency()?,
+            },
+        })
+    }
+
     /// Sleep for the given duration
     pub async fn sleep(&self, duration: Duration<usize>) -> TockResult<()> {
         let now = get_current_ticks()?;
@@ -400,6 +414,31 @@ impl<'a> ParallelSleepDriver<'a> {
         Ok(())
     }
 
+    /// Busy waits until `ticks` clock ticks have passed. Meant for delays
+    /// below the resolution of [sleep()](Self::sleep) when bit-banging
+    /// protocols, see [us_to_ticks()].
+    pub(crate) fn busy_wait_ticks(&self, ticks: usize) -> TockResult<()> {
+        spin_for_ticks(ticks)
+    }
+
+    /// Waits until `ticks` clock ticks have passed since the clock value
+    /// `start`. Whole milliseconds are slept so other tasks can run, only the
+    /// remainder below the resolution of [sleep()](Self::sleep) is busy
+    /// waited.
+    pub(crate) async fn wait_until_ticks(&self, start: usize, ticks: usize) -> TockResult<()> {
+        let freq = get_clock_frequency()?;
+        let remaining = ticks.saturating_sub(get_current_ticks()?.wrapping_sub(start));
+        let remaining_ms = if freq == 0 {
+            0
+        } else {
+            ticks_to_ms(remaining, freq)
+        };
+        if remaining_ms > 0 {
+            self.sleep(Duration::from_ms(remaining_ms)).await?;
+        }
+        spin_for_ticks(ticks.saturating_sub(get_current_ticks()?.wrapping_sub(start)))
+    }
+
     fn activate_timer(&self, timer: ActiveTimer) -> TockResult<()> {
         set_alarm_at(timer.instant as usize)?;
         let now = get_current_ticks()?;
@@ -491,6 +530,18 @@ impl<'a> ParallelSleepDriver<'a> {
     }
 }
 
+/// Busy waits for `duration` by polling the clock. Works without an
+/// activated timer driver, e.g. in a panic handler.
+pub(crate) fn busy_wait(duration: Duration<usize>) -> TockResult<()> {
+    spin_for_ticks(ms_to_ticks(duration.ms, get_clock_frequency()?))
+}
+
+fn spin_for_ticks(ticks: usize) -> TockResult<()> {
+    let start = get_current_ticks()?;
+    while get_current_ticks()?.wrapping_sub(start) < ticks {}
+    Ok(())
+}
+
 fn get_current_ticks() -> TockResult<usize> {
     syscalls::command(DRIVER_NUMBER, command_nr::GET_CLOCK_VALUE, 0, 0).map_err(|err| err.into())
 }
@@ -515,6 +566,31 @@ fn get_clock_frequency() -> TockResult<usize> {
         .map_err(|err| err.into())
 }
 
+/// Converts a duration in milliseconds into clock ticks, saturating at `usize::MAX`.
+pub(crate) fn ms_to_ticks(duration_ms: usize, freq: usize) -> usize {
+    ParallelSleepDriver::compute_alarm_instant(duration_ms, 0, freq).unwrap_or(core::usize::MAX)
+}
+
+/// Converts a number of clock ticks into milliseconds.
+pub(crate) fn ticks_to_ms(ticks: usize, freq: usize) -> usize {
+    match ticks.checked_mul(1000) {
+        Some(x) => x / freq,
+        None => 1000 * (ticks / freq),
+    }
+}
+
+/// Converts a duration in microseconds into the number of clock ticks to
+/// wait for. Adds one tick for non-zero durations as the current tick may
+/// already be almost over.
+pub(crate) fn us_to_ticks(duration_us: usize, freq: usize) -> usize {
+    let ticks = (duration_us as u64 * freq as u64 + 999_999) / 1_000_000;
+    if ticks == 0 {
+        0
+    } else {
+        ticks.min(core::usize::MAX as u64 - 1) as usize + 1
+    }
+}
+
 fn is_over(timer: ActiveTimer, now: u32) -> bool {
     now.wrapping_sub(timer.set_at) >= timer.instant.wrapping_sub(timer.set_at)
 }
@@ -568,6 +644,28 @@ mod test {
         assert!(x.is_err());
     }
 
+    #[test]
+    pub fn ticks_and_ms_round_trip() {
+        assert_eq!(super::ms_to_ticks(250, 32768), 8192);
+        assert_eq!(super::ticks_to_ms(8192, 32768), 250);
+        assert_eq!(
+            super::ms_to_ticks(core::usize::MAX, core::usize::MAX - 1),
+            core::usize::MAX
+        );
+        assert_eq!(
+            super::ticks_to_ms(core::usize::MAX, 1000),
+            core::usize::MAX / 1000 * 1000
+        );
+    }
+
+    #[test]
+    pub fn short_delays_round_up_to_ticks() {
+        assert_eq!(super::us_to_ticks(0, 32768), 0);
+        assert_eq!(super::us_to_ticks(60, 32768), 3);
+        assert_eq!(super::us_to_ticks(480, 32768), 17);
+        assert_eq!(super::us_to_ticks(1000, 0), 0);
+    }
+
     #[test]
     pub fn alarm_before_systick_wrap_expired() {
         assert_eq!(